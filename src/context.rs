@@ -1,6 +1,14 @@
-use crate::note_events::{MidiCC, Note};
-use std::{collections::{HashMap, HashSet}, fs::{File, OpenOptions}, fs, io::{Read, Write}};
+use crate::midi::VelocityCurve;
+use crate::note_events::Note;
+use crate::operators::read_operator_config;
+use crate::sampler::{sample_names, SAMPLES_DIR};
+use crate::utils::KEY_CHARS;
+use fundsp::hacker::{shared, Shared};
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+use std::{cell::RefCell, collections::{BTreeSet, HashMap, HashSet}, fs::File, fs, io::Read};
+use std::ops::Bound::{Excluded, Unbounded};
 use std::path::Path;
+use std::time::Instant;
 
 #[derive(Copy, Clone)]
 pub enum Mode {
@@ -13,6 +21,14 @@ pub enum Mode {
     Move,
 }
 
+// text typed into the status line after CTRL-g or CTRL-f, before enter applies it
+pub enum Prompt {
+    // row,col to move the cursor to
+    Jump(String),
+    // the glyph to find and then the glyph to replace it with
+    Replace(String),
+}
+
 #[derive(PartialEq, Copy, Clone)]
 pub enum AppState {
     Shutdown,
@@ -22,6 +38,7 @@ pub enum AppState {
 
 #[derive(Clone, Debug)]
 pub struct Port {
+    #[allow(dead_code)]
     pub name: String,
     pub row: i32,
     pub col: i32,
@@ -46,10 +63,13 @@ pub struct Globals {
 
 pub struct Context {
     pub grid: Vec<Vec<char>>,
+    // every cell inside rows x cols that isn't '.', in reading order, so a tick can skip empty cells;
+    // kept by write, and rebuilt by index_grid after the grid is replaced
+    pub occupied: BTreeSet<(i32, i32)>,
     pub notes: Vec<Note>,
-    pub cc: Vec<MidiCC>,
     pub locks: HashSet<(i32, i32)>,
     pub variables: HashMap<char, char>,
+    pub variables_snapshot: HashMap<char, char>,
     pub ticks: usize,
     pub tempo: u64,
     pub divisions: u64,
@@ -62,56 +82,187 @@ pub struct Context {
     pub global_key: char,
     pub midi_port: u8,
     pub midi_port_name: String,
+    pub seed: u64,
+    pub rng: RefCell<StdRng>,
+    pub incoming: Vec<(u8, u8, u8)>,
+    pub midi_clock: bool,
+    pub midi_panic: bool,
+    // follow incoming MIDI clock instead of the internal tempo
+    pub clock_follow: bool,
+    pub clock_pulses: u64,
+    // incoming notes bang twelve cells starting at this one, one column per pitch class
+    pub midi_bang_cell: Option<(i32, i32)>,
+    pub bang_notes: Vec<u8>,
+    pub held_notes: HashSet<u8>,
+    // shared with the synth and sampler output chains
+    pub master_volume: Shared<f64>,
+    // 0-100, how far every other tick is pushed late
+    pub swing: u8,
+    pub autosave_ticks: usize,
+    pub velocity_curve: VelocityCurve,
+    pub metronome: bool,
+    // wav file names per sampler bank, in the order the sample port indexes them
+    pub sample_names: Vec<Vec<String>>,
+    // a short confirmation for the status line and when it was set
+    pub status_message: Option<(String, Instant)>,
+    // rows and columns between the + markers and ruler labels
+    pub grid_spacing: usize,
+    // where operator glyphs are read from, operator_config.txt unless --config says otherwise
+    pub operator_config: String,
 }
 
-impl Context {
-    pub fn new(tempo: u64, divisions: u64, rows: usize, cols: usize, new_or_last: &str) -> Context {
-        // open last session or create a new empty grid
-        let grid: Vec<Vec<char>>;
+// pad a loaded grid into a rectangle of at least rows x cols so that indexing by grid[0].len() is safe
+fn normalize_grid(mut grid: Vec<Vec<char>>, rows: usize, cols: usize) -> Vec<Vec<char>> {
+    let width = grid.iter().map(|row| row.len()).max().unwrap_or(0).max(cols).max(1);
+    let height = grid.len().max(rows).max(1);
+    grid.resize(height, Vec::new());
+    for row in grid.iter_mut() {
+        row.resize(width, '.');
+    }
+    grid
+}
 
-        if new_or_last == "last" {
-            match File::open("last_session") {
-                Ok(mut session) => {
-                    let mut contents = String::new();
-                    session.read_to_string(&mut contents).expect("Unable to read file");
+// the random seed of a session is kept next to it, in <session>.seed
+fn read_seed(session_path: &str) -> Option<u64> {
+    fs::read_to_string(format!("{}.seed", session_path))
+        .ok()
+        .and_then(|seed| seed.trim().parse().ok())
+}
 
-                    grid = contents
-                        .lines()
-                        .map(|line| line.chars().collect())
-                        .collect();
-                }
-                _ => {
-                    grid = (0..rows)
-                        .map(|_| (0..cols).map(|_| '.').collect())
-                        .collect();
-                }
-            }
-        } else {
-            match File::open(new_or_last) {
-                Ok(mut session) => {
-                    let mut contents = String::new();
-                    session.read_to_string(&mut contents).expect("Unable to read file");
+// tempo, divisions, key, scale and volume of a session are kept next to it, one key=value per line in
+// <session>.settings; sessions saved without one keep the defaults
+fn read_settings(session_path: &str) -> HashMap<String, String> {
+    fs::read_to_string(format!("{}.settings", session_path))
+        .map(|settings| {
+            settings
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// glyphs the original ORCA uses for operators that have another glyph here, by the name of the operator
+// they become; ORCA operators with no counterpart here are dropped to empty cells
+const ORCA_GLYPHS: [(char, Option<&str>); 7] = [
+    (':', Some("Midi")),
+    ('%', Some("Midi")),
+    ('!', Some("MidiCC")),
+    ('?', Some("PitchBend")),
+    (';', None),
+    ('=', None),
+    ('$', None),
+];
+
+// reads an ORCA text grid, translating the glyphs listed above through the operator config
+fn import_orca_grid(contents: &str, operator_map: &HashMap<String, char>) -> Vec<Vec<char>> {
+    contents
+        .lines()
+        .map(|line| {
+            line.chars()
+                .map(|glyph| match ORCA_GLYPHS.iter().find(|(orca_glyph, _)| *orca_glyph == glyph) {
+                    Some((_, name)) => name.and_then(|name| operator_map.get(name).copied()).unwrap_or('.'),
+                    None => glyph,
+                })
+                .collect()
+        })
+        .collect()
+}
 
-                    grid = contents
+// the inverse of import_orca_grid: operators that ORCA knows under another glyph are translated, the
+// ones it doesn't have at all are cleared so the file stays playable there; every row loses its trailing
+// '.', and normalize_grid pads them back on import
+fn export_orca_grid(grid: &[Vec<char>], operator_map: &HashMap<String, char>) -> String {
+    let orca_glyph = |glyph: char| {
+        ORCA_GLYPHS
+            .iter()
+            .find(|(_, name)| name.and_then(|name| operator_map.get(name)) == Some(&glyph))
+            .map(|(orca_glyph, _)| *orca_glyph)
+    };
+    grid.iter()
+        .map(|row| {
+            row.iter()
+                .map(|&glyph| match glyph {
+                    'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '*' | '#' => glyph,
+                    _ => orca_glyph(glyph).unwrap_or('.'),
+                })
+                .collect::<String>()
+                .trim_end_matches('.')
+                .to_string()
+                + "\n"
+        })
+        .collect()
+}
+
+// where Context::new reads a session from: "last" is the session quit saves, a path ending in .orca is
+// imported as is, a file path is read as is, and a bare name is looked up where save writes it
+fn session_path(new_or_last: &str) -> String {
+    match new_or_last {
+        "last" => "orca/sessions/last_session".to_string(),
+        path if path.ends_with(".orca") => path.to_string(),
+        path if path.contains(std::path::is_separator) || Path::new(path).is_file() => path.to_string(),
+        name => format!("orca/sessions/{}", name.trim_matches('.')),
+    }
+}
+
+// writes what session_files collected, stopping at the first error
+pub fn write_session_files(files: &[(String, String)]) -> std::io::Result<()> {
+    for (path, contents) in files {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+impl Context {
+    pub fn new(
+        tempo: u64,
+        divisions: u64,
+        rows: usize,
+        cols: usize,
+        new_or_last: &str,
+        seed: Option<u64>,
+        operator_config: &str,
+    ) -> Context {
+        // open last session or a named one from the same folder save writes to, or create a new empty grid;
+        // a path ending in .orca is imported from the original ORCA and keeps its own size
+        let orca_import = new_or_last.ends_with(".orca");
+        let session_path = session_path(new_or_last);
+        let session = File::open(&session_path).ok().filter(|_| new_or_last != "new");
+        let grid: Vec<Vec<char>> = match session {
+            Some(mut session) => {
+                let mut contents = String::new();
+                session.read_to_string(&mut contents).expect("Unable to read file");
+
+                if orca_import {
+                    import_orca_grid(&contents, &read_operator_config(operator_config))
+                } else {
+                    contents
                         .lines()
                         .map(|line| line.chars().collect())
-                        .collect();
-                }
-                _ => {
-                    grid = (0..rows)
-                        .map(|_| (0..cols).map(|_| '.').collect())
-                        .collect();
+                        .collect()
                 }
             }
+            _ => (0..rows)
+                .map(|_| (0..cols).map(|_| '.').collect())
+                .collect(),
         };
+        let grid = if orca_import { normalize_grid(grid, 0, 0) } else { normalize_grid(grid, rows, cols) };
+        let (rows, cols) = (grid.len(), grid[0].len());
+        let seed = seed
+            .or_else(|| read_seed(&session_path))
+            .unwrap_or_else(|| thread_rng().gen());
 
-
-        Context {
+        let mut context = Context {
             grid,
+            occupied: BTreeSet::new(),
             notes: Vec::new(),
-            cc: Vec::new(),
             locks: HashSet::new(),
             variables: HashMap::new(),
+            variables_snapshot: HashMap::new(),
             ticks: 0,
             tempo,
             divisions,
@@ -124,6 +275,50 @@ impl Context {
             global_key: 'C',
             midi_port: 0,
             midi_port_name: String::new(),
+            seed,
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+            incoming: Vec::new(),
+            midi_clock: false,
+            midi_panic: false,
+            clock_follow: false,
+            clock_pulses: 0,
+            midi_bang_cell: None,
+            bang_notes: Vec::new(),
+            swing: 0,
+            autosave_ticks: 512,
+            velocity_curve: VelocityCurve::Linear,
+            metronome: false,
+            sample_names: sample_names(Path::new(SAMPLES_DIR)),
+            status_message: None,
+            grid_spacing: 9,
+            operator_config: operator_config.to_string(),
+            held_notes: HashSet::new(),
+            master_volume: shared(1.0),
+        };
+        context.index_grid();
+        if new_or_last != "new" {
+            context.apply_settings(&session_path);
+        }
+        context
+    }
+
+    fn apply_settings(&mut self, session_path: &str) {
+        let settings = read_settings(session_path);
+        if let Some(tempo) = settings.get("tempo").and_then(|tempo| tempo.parse().ok()) {
+            self.set_tempo(tempo);
+        }
+        if let Some(divisions) = settings.get("divisions").and_then(|divisions| divisions.parse().ok()) {
+            self.set_divisions(divisions);
+        }
+        // the key and scale name lookups fall back to C and Major, so they can't tell a bad value apart
+        if let Some(key) = settings.get("key").and_then(|key| key.chars().next()).filter(|key| KEY_CHARS.contains(key)) {
+            self.global_key = key;
+        }
+        if let Some(volume) = settings.get("volume").and_then(|volume| volume.parse().ok()) {
+            self.set_master_volume(volume);
+        }
+        if let Some(scale) = settings.get("scale").and_then(|scale| scale.chars().next()).filter(|scale| matches!(scale, '0'..='9' | 'a'..='p')) {
+            self.global_scale = scale;
         }
     }
 
@@ -160,42 +355,73 @@ impl Context {
             return;
         }
 
-        let row = row as usize;
-        let col = col as usize;
-
-        if let Some(row) = self.grid.get_mut(row) {
-            if let Some(cell) = row.get_mut(col) {
-                *cell = value;
+        if let Some(cell) = self.grid.get_mut(row as usize).and_then(|cells| cells.get_mut(col as usize)) {
+            *cell = value;
+            if value != '.' && (row as usize) < self.rows && (col as usize) < self.cols {
+                self.occupied.insert((row, col));
+            } else {
+                self.occupied.remove(&(row, col));
             }
         }
     }
 
-    pub fn save(&mut self, name: String) {
-        let dir_path = Path::new("orca/sessions");
-        if !dir_path.exists() {
-            fs::create_dir_all(dir_path).expect("Unable to create directory");
+    pub fn index_grid(&mut self) {
+        self.occupied = (0..self.rows.min(self.grid.len()))
+            .flat_map(|row| (0..self.cols.min(self.grid[row].len())).map(move |col| (row, col)))
+            .filter(|&(row, col)| self.grid[row][col] != '.')
+            .map(|(row, col)| (row as i32, col as i32))
+            .collect();
+    }
+
+    // the first occupied cell after the given one in reading order, or the first one at all;
+    // looked up afresh each step so cells written further on during a tick are still visited
+    pub fn next_occupied(&self, after: Option<(i32, i32)>) -> Option<(i32, i32)> {
+        match after {
+            Some(after) => self.occupied.range((Excluded(after), Unbounded)).next().copied(),
+            None => self.occupied.first().copied(),
         }
-        let file_name = format!("orca/sessions/{}", name.trim_matches('.'));
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(file_name)
-            .expect("Unable to open file");
+    }
 
-        let grid = self.grid.clone();
+    // the grid, seed and settings of a session as (path, contents), taken under the lock so the files can
+    // be written after it is released
+    pub fn session_files(&self, name: &str) -> Vec<(String, String)> {
+        let name = name.trim_matches('.');
+        let grid: String = self
+            .grid
+            .iter()
+            .map(|row| row.iter().chain(std::iter::once(&'\n')).collect::<String>())
+            .collect();
+        let settings = format!(
+            "tempo={}\ndivisions={}\nkey={}\nscale={}\nvolume={}\n",
+            self.tempo,
+            self.divisions,
+            self.global_key,
+            self.global_scale,
+            self.master_volume.value()
+        );
+        vec![
+            (format!("orca/sessions/{}", name), grid),
+            (format!("orca/sessions/{}.seed", name), self.seed.to_string()),
+            (format!("orca/sessions/{}.settings", name), settings),
+        ]
+    }
 
-        for row in grid {
-            let row_string: String = row.into_iter().collect();
-            file.write_all(row_string.as_bytes()).expect("Unable to write file");
-            file.write_all(b"\n").expect("Unable to write file");
-        }
+    pub fn save(&self, name: String) -> std::io::Result<()> {
+        write_session_files(&self.session_files(&name))
+    }
+
+    // writes the grid as orca/sessions/<name>.orca for the original ORCA, see export_orca_grid
+    pub fn export_orca(&self, name: &str) {
+        fs::create_dir_all("orca/sessions").expect("Unable to create directory");
+        let contents = export_orca_grid(&self.grid, &read_operator_config(&self.operator_config));
+        fs::write(format!("orca/sessions/{}.orca", name), contents).expect("Unable to write file");
     }
 
     pub fn load(&mut self, name: String) {
         if name != "buffer" {
             let file_name = format!("orca/sessions/{}", name.trim_matches('.'));
-            let mut file = File::open(file_name).unwrap_or(File::open("orca/sessions/buffer").expect("Unable to open file"));
+            let mut file = File::open(file_name)
+                .unwrap_or_else(|_| File::open("orca/sessions/buffer").expect("Unable to open file"));
             let mut contents = String::new();
             file.read_to_string(&mut contents).expect("Unable to read file");
 
@@ -204,11 +430,39 @@ impl Context {
                 .map(|line| line.chars().collect())
                 .collect();
 
-            self.grid = grid;
-        } else {
+            self.grid = normalize_grid(grid, self.rows, self.cols);
+            // a larger session grows the grid, so ticks and the UI cover all of it
+            (self.rows, self.cols) = (self.grid.len(), self.grid[0].len());
+            self.index_grid();
+
+            if let Some(seed) = read_seed(&format!("orca/sessions/{}", name.trim_matches('.'))) {
+                self.seed = seed;
+                self.rng = RefCell::new(StdRng::seed_from_u64(seed));
+            }
+            self.apply_settings(&format!("orca/sessions/{}", name.trim_matches('.')));
         }
     }
 
+    // note durations are counted in ticks, so tick_time has to follow the tempo; both factors stay
+    // above zero so the division is always defined
+    pub fn set_tempo(&mut self, tempo: u64) {
+        self.tempo = tempo.max(1);
+        self.tick_time = 60000 / (self.tempo * self.divisions);
+    }
+
+    pub fn set_divisions(&mut self, divisions: u64) {
+        self.divisions = divisions.max(1);
+        self.tick_time = 60000 / (self.tempo * self.divisions);
+    }
+
+    pub fn set_swing(&mut self, swing: u8) {
+        self.swing = swing.min(100);
+    }
+
+    pub fn set_master_volume(&mut self, volume: f64) {
+        self.master_volume.set_value(volume.clamp(0.0, 1.0));
+    }
+
     pub fn write_note(&mut self, note: Note) {
         self.notes.push(note);
     }
@@ -246,4 +500,319 @@ impl Context {
     pub fn unlock_all(&mut self) {
         self.locks = HashSet::new();
     }
+
+    // the row and column edits below shift cells under absolute coordinates, so locks and ports are
+    // dropped and rebuilt on the next tick
+    fn clear_locks_and_ports(&mut self) {
+        self.locks.clear();
+        self.ports.clear();
+    }
+
+    // inserts a blank row at row, pushing the last row off the grid
+    pub fn insert_row(&mut self, row: usize) {
+        let height = self.grid.len();
+        let width = self.grid.first().map_or(0, |r| r.len());
+        if row >= height {
+            return;
+        }
+        self.grid.insert(row, vec!['.'; width]);
+        self.grid.truncate(height);
+        self.clear_locks_and_ports();
+        self.index_grid();
+    }
+
+    // removes row and pads the bottom of the grid with a blank row
+    pub fn delete_row(&mut self, row: usize) {
+        let width = self.grid.first().map_or(0, |r| r.len());
+        if row >= self.grid.len() {
+            return;
+        }
+        self.grid.remove(row);
+        self.grid.push(vec!['.'; width]);
+        self.clear_locks_and_ports();
+        self.index_grid();
+    }
+
+    // inserts a blank column at col, pushing the last column off the grid
+    pub fn insert_column(&mut self, col: usize) {
+        for row in self.grid.iter_mut() {
+            let width = row.len();
+            if col < width {
+                row.insert(col, '.');
+                row.truncate(width);
+            }
+        }
+        self.clear_locks_and_ports();
+        self.index_grid();
+    }
+
+    // reallocates the grid to rows x cols, keeping the top-left content and padding with '.'
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        let (rows, cols) = (rows.max(1), cols.max(1));
+        self.grid.resize_with(rows, || vec!['.'; cols]);
+        for row in self.grid.iter_mut() {
+            row.resize(cols, '.');
+        }
+        self.rows = rows;
+        self.cols = cols;
+        self.clear_locks_and_ports();
+        self.index_grid();
+    }
+
+    // removes col and pads the right edge of the grid with a blank column
+    pub fn delete_column(&mut self, col: usize) {
+        for row in self.grid.iter_mut() {
+            if col < row.len() {
+                row.remove(col);
+                row.push('.');
+            }
+        }
+        self.clear_locks_and_ports();
+        self.index_grid();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_context(rows: usize, cols: usize) -> Context {
+        Context::new(120, 4, rows, cols, "new", Some(0), "operator_config.txt")
+    }
+
+    #[test]
+    fn orca_export_trims_rows_and_imports_back_to_the_same_grid() {
+        let operator_map = read_operator_config("operator_config.txt");
+        let grid: Vec<Vec<char>> = ["..D8....", "........", ".:03C...", "3Ab....."]
+            .iter()
+            .map(|row| row.chars().collect())
+            .collect();
+        let exported = export_orca_grid(&grid, &operator_map);
+        assert_eq!(exported, "..D8\n\n.:03C\n3Ab\n");
+
+        let imported = normalize_grid(import_orca_grid(&exported, &operator_map), 4, 8);
+        assert_eq!(imported, grid);
+    }
+
+    #[test]
+    fn orca_export_clears_operators_orca_lacks() {
+        let operator_map = read_operator_config("operator_config.txt");
+        let grid = vec!["±1.".chars().collect()];
+        assert_eq!(export_orca_grid(&grid, &operator_map), ".1\n");
+    }
+
+    #[test]
+    fn session_files_hold_grid_seed_and_settings() {
+        let mut context = empty_context(2, 3);
+        context.write(0, 1, 'D');
+        let files = context.session_files("set.");
+        assert_eq!(files[0], ("orca/sessions/set".to_string(), ".D.\n...\n".to_string()));
+        assert_eq!(files[1], ("orca/sessions/set.seed".to_string(), "0".to_string()));
+        assert_eq!(files[2].0, "orca/sessions/set.settings");
+        assert!(files[2].1.starts_with("tempo=120\ndivisions=4\n"));
+    }
+
+    #[test]
+    fn write_session_files_returns_errors_instead_of_panicking() {
+        let dir = std::env::temp_dir().join(format!("orca-rs-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let blocker = dir.join("not_a_dir");
+        fs::write(&blocker, "").unwrap();
+        let inside_file = blocker.join("session").to_string_lossy().to_string();
+        assert!(write_session_files(&[(inside_file, String::new())]).is_err());
+
+        let session = dir.join("sessions/set").to_string_lossy().to_string();
+        write_session_files(&[(session.clone(), "*\n".to_string())]).unwrap();
+        assert_eq!(fs::read_to_string(&session).unwrap(), "*\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // saves under a name no one uses and cleans up after itself, so tests don't touch real sessions
+    fn save_and_reload(context: &Context, name: &str) -> Context {
+        let name = format!("{}-{}", name, std::process::id());
+        context.save(name.clone()).unwrap();
+        let reloaded = Context::new(120, 4, context.rows, context.cols, &name, None, "operator_config.txt");
+        for (path, _) in context.session_files(&name) {
+            fs::remove_file(path).unwrap();
+        }
+        reloaded
+    }
+
+    #[test]
+    fn last_is_read_from_where_quit_saves_it() {
+        let context = empty_context(1, 1);
+        assert_eq!(session_path("last"), context.session_files("last_session")[0].0);
+    }
+
+    #[test]
+    fn a_saved_grid_loads_back() {
+        let mut context = empty_context(3, 4);
+        context.write(0, 0, 'D');
+        context.write(2, 3, '*');
+        let reloaded = save_and_reload(&context, "test-round-trip");
+        assert_eq!(reloaded.grid, context.grid);
+        assert_eq!(reloaded.seed, context.seed);
+    }
+
+    #[test]
+    fn a_short_ragged_session_is_padded_into_a_rectangle() {
+        let name = format!("test-ragged-{}", std::process::id());
+        let path = format!("orca/sessions/{}", name);
+        write_session_files(&[(path.clone(), "D\n\n..*.\n".to_string())]).unwrap();
+        let context = Context::new(120, 4, 5, 3, &name, Some(0), "operator_config.txt");
+        fs::remove_file(path).unwrap();
+
+        assert_eq!((context.rows, context.cols), (5, 4));
+        assert!(context.grid.iter().all(|row| row.len() == 4));
+        assert_eq!(context.grid[0], ['D', '.', '.', '.']);
+        assert_eq!(context.grid[2], ['.', '.', '*', '.']);
+        assert_eq!(context.grid[4], ['.'; 4]);
+    }
+
+    #[test]
+    fn an_empty_grid_still_has_a_cell() {
+        assert_eq!(normalize_grid(Vec::new(), 0, 0), [['.']]);
+    }
+
+    fn rows(context: &Context) -> Vec<String> {
+        context.grid.iter().map(|row| row.iter().collect()).collect()
+    }
+
+    #[test]
+    fn inserting_in_the_middle_and_deleting_at_the_edge_keep_the_grid_size() {
+        let mut context = empty_context(3, 3);
+        for (row, line) in ["abc", "def", "ghi"].iter().enumerate() {
+            for (col, glyph) in line.chars().enumerate() {
+                context.write(row as i32, col as i32, glyph);
+            }
+        }
+
+        context.insert_row(1);
+        assert_eq!(rows(&context), ["abc", "...", "def"]);
+        context.delete_row(2);
+        assert_eq!(rows(&context), ["abc", "...", "..."]);
+        context.insert_column(1);
+        assert_eq!(rows(&context), ["a.b", "...", "..."]);
+        context.delete_column(2);
+        assert_eq!(rows(&context), ["a..", "...", "..."]);
+
+        // rows and columns past the grid are left alone
+        context.insert_row(3);
+        context.delete_column(3);
+        assert_eq!(rows(&context), ["a..", "...", "..."]);
+    }
+
+    #[test]
+    fn named_sessions_are_read_from_where_they_are_written() {
+        let mut context = empty_context(2, 3);
+        context.write(1, 2, 'C');
+        let name = format!("test-named-{}", std::process::id());
+        let files = context.session_files(&name);
+        assert_eq!(files[0].0, session_path(&name));
+        assert!(files[0].0.starts_with("orca/sessions/"));
+
+        write_session_files(&files).unwrap();
+        let reloaded = Context::new(120, 4, 2, 3, &name, None, "operator_config.txt");
+        for (path, _) in files {
+            fs::remove_file(path).unwrap();
+        }
+        assert_eq!(reloaded.grid, context.grid);
+    }
+
+    #[test]
+    fn session_settings_load_back_and_default_when_missing() {
+        let mut context = empty_context(2, 2);
+        context.set_tempo(97);
+        context.set_divisions(3);
+        context.global_key = 'D';
+        context.global_scale = '2';
+        context.set_master_volume(0.5);
+        let reloaded = save_and_reload(&context, "test-settings");
+        assert_eq!((reloaded.tempo, reloaded.divisions, reloaded.tick_time), (97, 3, 206));
+        assert_eq!((reloaded.global_key, reloaded.global_scale), ('D', '2'));
+        assert_eq!(reloaded.master_volume.value(), 0.5);
+
+        // a session saved before settings were kept loads with the defaults
+        let name = format!("test-no-settings-{}", std::process::id());
+        let files = context.session_files(&name);
+        write_session_files(&files[..1]).unwrap();
+        let reloaded = Context::new(120, 4, 2, 2, &name, None, "operator_config.txt");
+        fs::remove_file(&files[0].0).unwrap();
+        assert_eq!((reloaded.tempo, reloaded.divisions, reloaded.tick_time), (120, 4, 125));
+        assert_eq!(reloaded.grid, context.grid);
+    }
+
+    #[test]
+    fn unreadable_settings_fall_back_one_by_one() {
+        let context = empty_context(2, 2);
+        let name = format!("test-bad-settings-{}", std::process::id());
+        let mut files = context.session_files(&name);
+        files[2].1 = "tempo = 90\ndivisions=fast\nkey=?\nscale=z\nnot a setting\n".to_string();
+        write_session_files(&files).unwrap();
+        let reloaded = Context::new(120, 4, 2, 2, &name, None, "operator_config.txt");
+        for (path, _) in files {
+            fs::remove_file(path).unwrap();
+        }
+        assert_eq!((reloaded.tempo, reloaded.divisions), (90, 4));
+        assert_eq!((reloaded.global_key, reloaded.global_scale), (context.global_key, context.global_scale));
+    }
+
+    #[test]
+    fn an_orca_file_imports_at_its_own_size_with_operators_translated() {
+        let path = std::env::temp_dir().join(format!("orca-rs-import-{}.orca", std::process::id()));
+        fs::write(&path, "..D4\n.1A2\n:03C.;\n.!..\n").unwrap();
+        let context = Context::new(120, 4, 20, 30, path.to_str().unwrap(), Some(0), "operator_config.txt");
+        fs::remove_file(&path).unwrap();
+
+        let operator_map = read_operator_config("operator_config.txt");
+        assert_eq!((context.rows, context.cols), (4, 6));
+        assert_eq!(context.grid[0][2], 'D');
+        assert_eq!(context.grid[1][2], 'A');
+        assert!(crate::operators::get_tick_operators(&operator_map).contains_key(&context.grid[1][2]));
+        assert_eq!(context.grid[2][0], operator_map["Midi"]);
+        assert_eq!(context.grid[2][5], '.');
+        assert_eq!(context.grid[3][1], operator_map["MidiCC"]);
+        assert_eq!(context.grid[3][5], '.');
+    }
+
+    #[test]
+    fn loading_a_larger_session_ticks_all_of_it() {
+        use crate::operators::{get_bang_operators, get_tick_operators, grid_tick, GlyphTable};
+
+        let mut larger = empty_context(5, 6);
+        larger.write(3, 4, 'C');
+        larger.write(3, 5, '5');
+        let name = format!("test-larger-{}", std::process::id());
+        let files = larger.session_files(&name);
+        write_session_files(&files).unwrap();
+        let mut context = empty_context(2, 3);
+        context.load(name);
+        for (path, _) in files {
+            fs::remove_file(path).unwrap();
+        }
+        assert_eq!((context.rows, context.cols), (5, 6));
+
+        let operator_map = read_operator_config("operator_config.txt");
+        let (tick_operators, bang_operators) = (get_tick_operators(&operator_map), get_bang_operators(&operator_map));
+        let (tick_glyphs, bang_glyphs) = (GlyphTable::new(&tick_operators), GlyphTable::new(&bang_operators));
+        context.ticks = 3;
+        let should_redraw = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        grid_tick(&mut context, &tick_operators, &bang_operators, &tick_glyphs, &bang_glyphs, should_redraw);
+        assert_eq!(context.read(4, 4), '3');
+    }
+
+    #[test]
+    fn a_session_given_as_a_file_path_is_read_from_there() {
+        let path = std::env::temp_dir().join(format!("orca-rs-set-{}.txt", std::process::id()));
+        fs::write(&path, "D4\n.*\n").unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(session_path(path), path);
+        let context = Context::new(120, 4, 2, 2, path, Some(0), "operator_config.txt");
+        fs::remove_file(path).unwrap();
+        assert_eq!(rows(&context), ["D4", ".*"]);
+
+        // relative paths stay as they are, bare names are still looked up in the sessions folder
+        assert_eq!(session_path("../set"), "../set");
+        assert_eq!(session_path("set."), "orca/sessions/set");
+    }
 }
@@ -1,20 +1,26 @@
-use std::{fs, fs::OpenOptions, io::Write, sync::{
+use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
-}};
-use std::path::Path;
+};
+use std::time::Instant;
 
 use copypasta::{ClipboardContext, ClipboardProvider};
+use crossbeam::channel::Sender;
 use crossterm::{
-    event::{Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{DisableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
+    execute,
     terminal::disable_raw_mode,
 };
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
 
-use crate::context::{AppState, Context, Mode};
+use crate::context::{AppState, Context, Mode, Prompt};
+use crate::keybindings::{Action, Keybindings};
+use crate::operators::{base_36_to_char, char_to_base_36};
+use crate::ui::{grid_cell_at, Viewport};
 use crate::{Cursor, RowsCols};
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_events(
     should_redraw: &Arc<AtomicBool>,
     context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
@@ -23,123 +29,382 @@ pub fn handle_events(
     selected_cells: &mut Option<Vec<Vec<char>>>,
     cursor: &mut Cursor,
     show_popup: &mut bool,
-    rows_cols: &RowsCols,
+    show_variables: &mut bool,
+    rows_cols: &mut RowsCols,
+    sample_reload_sender: &Sender<()>,
+    viewport: &Viewport,
+    prompt: &mut Option<Prompt>,
+    keybindings: &Keybindings,
 ) {
-    match crossterm::event::read().expect("Failed to read event") {
-        Event::Key(KeyEvent {
-                       code, modifiers, ..
-                   }) => {
+    let event = crossterm::event::read().expect("Failed to read event");
+    if let Event::Mouse(mouse_event) = event {
+        // plain mouse movement is reported too, but only clicks and drags change anything
+        if mouse(mouse_event, mode, cursor, rows_cols, viewport) {
             should_redraw.store(true, Ordering::Relaxed);
-            match code {
-                KeyCode::Char('=') => {
-                    tempo_up(context_arc);
-                }
+            *show_popup = false;
+        }
+    } else if let Event::Resize(..) = event {
+        // the viewport is recomputed from the new size on the next draw
+        should_redraw.store(true, Ordering::Relaxed);
+    } else if let Event::Key(KeyEvent { code, modifiers, .. }) = event {
+        should_redraw.store(true, Ordering::Relaxed);
+        // keys bound in keybindings.txt run their action, anything else moves the cursor or is typed
+        match (keybindings.action(code, modifiers), code) {
+            // while a prompt is open every key goes to its input
+            _ if matches!(prompt, Some(Prompt::Jump(_))) => {
+                jump_key(code, prompt, cursor, rows_cols);
+            }
 
-                KeyCode::Char('-') => {
-                    tempo_down(context_arc);
-                }
+            _ if matches!(prompt, Some(Prompt::Replace(_))) => {
+                replace_key(code, modifiers, prompt, mode, context_arc);
+            }
 
-                KeyCode::Char('q') if modifiers == KeyModifiers::CONTROL => {
-                    quit(context_arc, terminal);
-                }
+            (Some(Action::Jump), _) => {
+                *show_popup = false;
+                *prompt = Some(Prompt::Jump(String::new()));
+            }
 
-                KeyCode::Char('c') if modifiers == KeyModifiers::CONTROL => {
-                    copy(mode, context_arc, selected_cells);
-                }
+            (Some(Action::Replace), _) => {
+                *show_popup = false;
+                *prompt = Some(Prompt::Replace(String::new()));
+            }
 
-                KeyCode::Char('v') if modifiers == KeyModifiers::CONTROL => {
-                    paste(context_arc, *cursor.cursor_row, *cursor.cursor_col, mode);
-                }
+            (Some(Action::TempoUp), _) => {
+                tempo_up(context_arc);
+            }
 
-                KeyCode::Char('h') if modifiers == KeyModifiers::CONTROL => {
-                    *show_popup = !*show_popup;
-                }
+            (Some(Action::TempoDown), _) => {
+                tempo_down(context_arc);
+            }
 
-                KeyCode::Char('d') if modifiers == KeyModifiers::CONTROL => {
-                    clear_grid(context_arc, rows_cols.rows, rows_cols.cols);
-                }
+            (Some(Action::DivisionsUp), _) => {
+                divisions_up(context_arc);
+            }
 
-                KeyCode::Char(' ') => {
-                    pause(context_arc);
-                }
+            (Some(Action::DivisionsDown), _) => {
+                divisions_down(context_arc);
+            }
 
-                KeyCode::Char('p') if modifiers == KeyModifiers::CONTROL => {
-                    change_midi_port(context_arc);
-                }
+            (Some(Action::Quit), _) => {
+                quit(context_arc, terminal);
+            }
 
-                KeyCode::Up => {
-                    *show_popup = false;
-                    cursor_up(
-                        cursor.cursor_row,
-                        mode,
-                        &*selected_cells,
-                        context_arc,
-                        *cursor.cursor_col
-                    );
-                }
+            (Some(Action::Copy), _) => {
+                copy(mode, context_arc, selected_cells);
+            }
 
-                KeyCode::Down => {
-                    *show_popup = false;
-                    cursor_down(
-                        cursor.cursor_row,
-                        mode,
-                        rows_cols.rows,
-                        &*selected_cells,
-                        context_arc,
-                        *cursor.cursor_col,
-                    );
-                }
+            (Some(Action::Paste), _) => {
+                paste(context_arc, *cursor.cursor_row, *cursor.cursor_col, mode, 0);
+            }
 
-                KeyCode::Left => {
-                    *show_popup = false;
-                    cursor_left(
-                        cursor.cursor_col,
-                        mode,
-                        &*selected_cells,
-                        context_arc,
-                        *cursor.cursor_row
-                    );
-                }
+            (Some(Action::PasteUp), _) => {
+                paste(context_arc, *cursor.cursor_row, *cursor.cursor_col, mode, 1);
+            }
 
-                KeyCode::Right => {
-                    *show_popup = false;
-                    cursor_right(
-                        cursor.cursor_col,
-                        mode,
-                        rows_cols.cols,
-                        &*selected_cells,
-                        context_arc,
-                        *cursor.cursor_row,
-                    );
-                }
+            (Some(Action::PasteDown), _) => {
+                paste(context_arc, *cursor.cursor_row, *cursor.cursor_col, mode, -1);
+            }
 
-                KeyCode::Char(c) => {
-                    input_char(
-                        c,
-                        mode,
-                        cursor.cursor_row,
-                        cursor.cursor_col,
-                        context_arc,
-                        selected_cells
-                    );
-                }
+            (Some(Action::InsertRow), _) => {
+                context_arc.lock().insert_row(*cursor.cursor_row);
+            }
 
-                KeyCode::Esc => {
-                    *show_popup = false;
-                    escape(mode);
-                }
+            (Some(Action::DeleteRow), _) => {
+                context_arc.lock().delete_row(*cursor.cursor_row);
+            }
 
-                KeyCode::Backspace => {
-                    backspace(mode, context_arc, *cursor.cursor_row, *cursor.cursor_col);
-                }
-                _ => {}
+            (Some(Action::InsertColumn), _) => {
+                context_arc.lock().insert_column(*cursor.cursor_col);
+            }
+
+            (Some(Action::DeleteColumn), _) => {
+                context_arc.lock().delete_column(*cursor.cursor_col);
+            }
+
+            (Some(Action::FlipHorizontal), _) => {
+                flip_selection(mode, context_arc, false);
+            }
+
+            (Some(Action::FlipVertical), _) => {
+                flip_selection(mode, context_arc, true);
+            }
+
+            (Some(Action::Rotate), _) => {
+                rotate_selection(mode, context_arc);
+            }
+
+            (Some(Action::Variables), _) => {
+                *show_variables = !*show_variables;
+            }
+
+            (Some(Action::Help), _) => {
+                *show_popup = !*show_popup;
+            }
+
+            (Some(Action::Clear), _) => {
+                clear_grid(context_arc, rows_cols.rows, rows_cols.cols);
+            }
+
+            (Some(Action::Pause), _) => {
+                pause(context_arc);
+            }
+
+            (Some(Action::MidiPort), _) => {
+                change_midi_port(context_arc);
+            }
+
+            (Some(Action::MidiClock), _) => {
+                toggle_midi_clock(context_arc);
+            }
+
+            (Some(Action::ReloadSamples), _) => {
+                reload_samples(context_arc, sample_reload_sender);
+            }
+
+            (Some(Action::VelocityCurve), _) => {
+                let mut context = context_arc.lock();
+                context.velocity_curve = context.velocity_curve.next();
+            }
+
+            (Some(Action::Metronome), _) => {
+                let mut context = context_arc.lock();
+                context.metronome = !context.metronome;
+            }
+
+            (Some(Action::Export), _) => {
+                context_arc.lock().export_orca("export");
+            }
+
+            (Some(Action::Panic), _) => {
+                midi_panic(context_arc);
+            }
+
+            (Some(Action::SwingUp), _) => {
+                swing_up(context_arc);
+            }
+
+            (Some(Action::SwingDown), _) => {
+                swing_down(context_arc);
+            }
+
+            (Some(Action::VolumeUp), _) => {
+                volume_up(context_arc);
+            }
+
+            (Some(Action::VolumeDown), _) => {
+                volume_down(context_arc);
+            }
+
+            (Some(Action::RemoveRow), _) => {
+                resize_grid(context_arc, rows_cols, cursor, -1, 0);
+            }
+
+            (Some(Action::AddRow), _) => {
+                resize_grid(context_arc, rows_cols, cursor, 1, 0);
+            }
+
+            (Some(Action::RemoveColumn), _) => {
+                resize_grid(context_arc, rows_cols, cursor, 0, -1);
+            }
+
+            (Some(Action::AddColumn), _) => {
+                resize_grid(context_arc, rows_cols, cursor, 0, 1);
+            }
+
+            (Some(Action::StepUp), _) => {
+                step_glyph(context_arc, *cursor.cursor_row, *cursor.cursor_col, 1);
+            }
+
+            (Some(Action::StepDown), _) => {
+                step_glyph(context_arc, *cursor.cursor_row, *cursor.cursor_col, -1);
+            }
+
+            (_, KeyCode::Up) => {
+                *show_popup = false;
+                cursor_up(
+                    cursor.cursor_row,
+                    mode,
+                    &*selected_cells,
+                    context_arc,
+                    *cursor.cursor_col
+                );
+            }
+
+            (_, KeyCode::Down) => {
+                *show_popup = false;
+                cursor_down(
+                    cursor.cursor_row,
+                    mode,
+                    rows_cols.rows,
+                    &*selected_cells,
+                    context_arc,
+                    *cursor.cursor_col,
+                );
+            }
+
+            (_, KeyCode::Left) => {
+                *show_popup = false;
+                cursor_left(
+                    cursor.cursor_col,
+                    mode,
+                    &*selected_cells,
+                    context_arc,
+                    *cursor.cursor_row
+                );
+            }
+
+            (_, KeyCode::Right) => {
+                *show_popup = false;
+                cursor_right(
+                    cursor.cursor_col,
+                    mode,
+                    rows_cols.cols,
+                    &*selected_cells,
+                    context_arc,
+                    *cursor.cursor_row,
+                );
             }
+
+            (_, KeyCode::Char(c)) => {
+                input_char(
+                    c,
+                    mode,
+                    cursor.cursor_row,
+                    cursor.cursor_col,
+                    context_arc,
+                    selected_cells
+                );
+            }
+
+            (_, KeyCode::Esc) => {
+                *show_popup = false;
+                escape(mode);
+            }
+
+            (_, KeyCode::Backspace) => {
+                backspace(mode, context_arc, *cursor.cursor_row, *cursor.cursor_col);
+            }
+            _ => {}
         }
+    }
+}
 
+// edits the row,col being typed after CTRL-g; enter jumps there, malformed input is ignored
+pub fn jump_key(code: KeyCode, prompt: &mut Option<Prompt>, cursor: &mut Cursor, rows_cols: &RowsCols) {
+    let Some(Prompt::Jump(input)) = prompt else {
+        return;
+    };
+    match code {
+        KeyCode::Char(c) if c.is_ascii_digit() || c == ',' => {
+            input.push(c);
+        }
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Enter => {
+            if let Some((row, col)) = input
+                .split_once(',')
+                .and_then(|(row, col)| Some((row.trim().parse::<usize>().ok()?, col.trim().parse::<usize>().ok()?)))
+            {
+                *cursor.cursor_row = row.min(rows_cols.rows.saturating_sub(1));
+                *cursor.cursor_col = col.min(rows_cols.cols.saturating_sub(1));
+            }
+            *prompt = None;
+        }
+        KeyCode::Esc => {
+            *prompt = None;
+        }
         _ => {}
     }
 }
 
+// takes the glyph to find and the one to put in its place; enter replaces every match, in the selection if
+// there is one, and ALT-enter leaves cells locked this tick alone
+pub fn replace_key(
+    code: KeyCode,
+    modifiers: KeyModifiers,
+    prompt: &mut Option<Prompt>,
+    mode: &mut Mode,
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+) {
+    let Some(Prompt::Replace(input)) = prompt else {
+        return;
+    };
+    match code {
+        KeyCode::Char(c) if input.chars().count() < 2 => {
+            input.push(c);
+        }
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Enter => {
+            let mut glyphs = input.chars();
+            if let (Some(from), Some(to)) = (glyphs.next(), glyphs.next()) {
+                replace_glyph(context_arc, mode, from, to, modifiers.contains(KeyModifiers::ALT));
+            }
+            *prompt = None;
+        }
+        KeyCode::Esc => {
+            *prompt = None;
+        }
+        _ => {}
+    }
+}
+
+pub fn replace_glyph(
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+    mode: &mut Mode,
+    from: char,
+    to: char,
+    skip_locked: bool,
+) {
+    let mut context = context_arc.lock();
+    let (rows, cols) = match *mode {
+        Mode::Select { start, end } => (start.0.min(end.0)..=start.0.max(end.0), start.1.min(end.1)..=start.1.max(end.1)),
+        _ => (0..=context.rows.saturating_sub(1), 0..=context.cols.saturating_sub(1)),
+    };
+    let matches: Vec<(i32, i32)> = rows
+        .flat_map(|row| cols.clone().map(move |col| (row as i32, col as i32)))
+        .filter(|&(row, col)| context.read(row, col) == from)
+        .filter(|cell| !skip_locked || !context.locks.contains(cell))
+        .collect();
+    for &(row, col) in &matches {
+        context.write(row, col, to);
+    }
+    context.status_message = Some((format!("replaced {} {} with {}", matches.len(), from, to), Instant::now()));
+}
+
+// a click moves the cursor, dragging selects from where the drag started
+pub fn mouse(
+    mouse_event: MouseEvent,
+    mode: &mut Mode,
+    cursor: &mut Cursor,
+    rows_cols: &RowsCols,
+    viewport: &Viewport,
+) -> bool {
+    let Some((row, col)) = grid_cell_at(mouse_event.column, mouse_event.row, viewport, rows_cols.rows, rows_cols.cols) else {
+        return false;
+    };
+    match (mouse_event.kind, *mode) {
+        (MouseEventKind::Down(MouseButton::Left), Mode::Normal | Mode::Select { .. }) => {
+            *mode = Mode::Normal;
+        }
+        (MouseEventKind::Drag(MouseButton::Left), Mode::Normal) => {
+            *mode = Mode::Select {
+                start: (*cursor.cursor_row, *cursor.cursor_col),
+                end: (row, col),
+            };
+        }
+        (MouseEventKind::Drag(MouseButton::Left), Mode::Select { start, .. }) => {
+            *mode = Mode::Select { start, end: (row, col) };
+        }
+        _ => return false,
+    }
+    *cursor.cursor_row = row;
+    *cursor.cursor_col = col;
+    true
+}
+
 pub fn cursor_up(
     cursor_row: &mut usize,
     mode: &mut Mode,
@@ -159,7 +424,7 @@ pub fn cursor_up(
                         let target_row = *cursor_row + r;
                         let target_col = cursor_col + c;
                         if target_row <= max_row_index && target_col <= max_col_index {
-                            context.grid[target_row][target_col] = value;
+                            context.write(target_row as i32, target_col as i32, value);
                         }
                     }
                 }
@@ -193,7 +458,7 @@ pub fn cursor_down(
                         let target_row = *cursor_row + r;
                         let target_col = cursor_col + c;
                         if target_row <= max_row_index && target_col <= max_col_index {
-                            context.grid[target_row][target_col] = value;
+                            context.write(target_row as i32, target_col as i32, value);
                         }
                     }
                 }
@@ -228,7 +493,7 @@ pub fn cursor_left(
                         let target_row = cursor_row + r;
                         let target_col = *cursor_col + c;
                         if target_row <= max_row_index && target_col <= max_col_index {
-                            context.grid[target_row][target_col] = value;
+                            context.write(target_row as i32, target_col as i32, value);
                         }
                     }
                 }
@@ -262,7 +527,7 @@ pub fn cursor_right(
                         let target_row = cursor_row + r;
                         let target_col = *cursor_col + c;
                         if target_row <= max_row_index && target_col <= max_col_index {
-                            context.grid[target_row][target_col] = value;
+                            context.write(target_row as i32, target_col as i32, value);
                         }
                     }
                 }
@@ -299,32 +564,39 @@ pub fn input_char(
             }
             _ => {}
         }
-    } else if c == '/' {
-        if let Mode::Select { start, end } = *mode {
-            let context = context_arc.lock();
-            let min_row = start.0.min(end.0);
-            let max_row = start.0.max(end.0);
-            let min_col = start.1.min(end.1);
-            let max_col = start.1.max(end.1);
+    } else if let (Mode::Select { start, end }, '/') = (*mode, c) {
+        let context = context_arc.lock();
+        let min_row = start.0.min(end.0);
+        let max_row = start.0.max(end.0);
+        let min_col = start.1.min(end.1);
+        let max_col = start.1.max(end.1);
 
-            let mut moved_cells = vec![];
+        let mut moved_cells = vec![];
 
-            for row in min_row..=max_row {
-                let mut moved_row = vec![];
-                for col in min_col..=max_col {
-                    moved_row.push(context.grid[row][col]);
-                }
-                moved_cells.push(moved_row);
+        for row in min_row..=max_row {
+            let mut moved_row = vec![];
+            for col in min_col..=max_col {
+                moved_row.push(context.grid[row][col]);
             }
+            moved_cells.push(moved_row);
+        }
 
-            *selected_cells = Some(moved_cells);
-            *mode = Mode::Move;
-            *cursor_row = min_row;
-            *cursor_col = min_col;
+        *selected_cells = Some(moved_cells);
+        *mode = Mode::Move;
+        *cursor_row = min_row;
+        *cursor_col = min_col;
+    } else if let Mode::Select { start, end } = *mode {
+        // typing over a selection stamps the glyph into every selected cell
+        let mut context = context_arc.lock();
+        for row in start.0.min(end.0)..=start.0.max(end.0) {
+            for col in start.1.min(end.1)..=start.1.max(end.1) {
+                context.write(row as i32, col as i32, c);
+            }
         }
+        *mode = Mode::Normal;
     } else {
         let mut _context = context_arc.lock();
-        _context.grid[*cursor_row][*cursor_col] = c;
+        _context.write(*cursor_row as i32, *cursor_col as i32, c);
     }
 }
 
@@ -343,16 +615,136 @@ pub fn backspace(
 
         for row in min_row..=max_row {
             for col in min_col..=max_col {
-                context.grid[row][col] = '.';
+                context.write(row as i32, col as i32, '.');
             }
         }
         *mode = Mode::Normal;
     } else {
         let mut _context = context_arc.lock();
-        _context.grid[cursor_row][cursor_col] = '.';
+        _context.write(cursor_row as i32, cursor_col as i32, '.');
+    }
+}
+
+// nudges the base-36 value under the cursor up or down, wrapping z to 0 and keeping its case
+pub fn step_glyph(
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+    cursor_row: usize,
+    cursor_col: usize,
+    step: i32,
+) {
+    let mut context = context_arc.lock();
+    let value = context.grid[cursor_row][cursor_col];
+    if value.is_ascii_alphanumeric() {
+        let (base_36, upper) = char_to_base_36(value);
+        context.write(cursor_row as i32, cursor_col as i32, base_36_to_char((base_36 as i32 + step).rem_euclid(36) as u8, upper));
+    }
+}
+
+// mirrors the selected rectangle left to right, or top to bottom when vertical, turning E/W or N/S
+// around so the mirrored pattern moves the same way
+pub fn flip_selection(
+    mode: &Mode,
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+    vertical: bool,
+) {
+    if let Mode::Select { start, end } = *mode {
+        let mut context = context_arc.lock();
+        let min_row = start.0.min(end.0);
+        let max_row = start.0.max(end.0);
+        let min_col = start.1.min(end.1);
+        let max_col = start.1.max(end.1);
+
+        let flipped: Vec<Vec<char>> = (min_row..=max_row)
+            .map(|row| {
+                (min_col..=max_col)
+                    .map(|col| {
+                        let (source_row, source_col) = if vertical {
+                            (max_row + min_row - row, col)
+                        } else {
+                            (row, max_col + min_col - col)
+                        };
+                        match (context.grid[source_row][source_col], vertical) {
+                            ('E', false) => 'W',
+                            ('W', false) => 'E',
+                            ('N', true) => 'S',
+                            ('S', true) => 'N',
+                            (value, _) => value,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for (r, row) in flipped.into_iter().enumerate() {
+            for (c, value) in row.into_iter().enumerate() {
+                context.write((min_row + r) as i32, (min_col + c) as i32, value);
+            }
+        }
     }
 }
 
+// turns the selected rectangle 90 degrees clockwise around its top left corner; a non-square
+// selection takes its new bounding box, clipped at the grid edges, and the selection follows it
+pub fn rotate_selection(
+    mode: &mut Mode,
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+) {
+    if let Mode::Select { start, end } = *mode {
+        let mut context = context_arc.lock();
+        let min_row = start.0.min(end.0);
+        let max_row = start.0.max(end.0);
+        let min_col = start.1.min(end.1);
+        let max_col = start.1.max(end.1);
+
+        let mut selected = vec![];
+        for row in min_row..=max_row {
+            let mut selected_row = vec![];
+            for col in min_col..=max_col {
+                selected_row.push(context.grid[row][col]);
+                context.write(row as i32, col as i32, '.');
+            }
+            selected.push(selected_row);
+        }
+
+        let height = selected.len();
+        let width = selected[0].len();
+        let max_row_index = context.grid.len() - 1;
+        let max_col_index = context.grid[0].len() - 1;
+        for (r, row) in selected.iter().enumerate() {
+            for (c, &value) in row.iter().enumerate() {
+                let target_row = min_row + c;
+                let target_col = min_col + height - 1 - r;
+                if target_row <= max_row_index && target_col <= max_col_index {
+                    context.write(target_row as i32, target_col as i32, value);
+                }
+            }
+        }
+
+        *mode = Mode::Select {
+            start: (min_row, min_col),
+            end: ((min_row + width - 1).min(max_row_index), (min_col + height - 1).min(max_col_index)),
+        };
+    }
+}
+
+// grows or shrinks the grid by a row or column and keeps the cursor inside it
+pub fn resize_grid(
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+    rows_cols: &mut RowsCols,
+    cursor: &mut Cursor,
+    row_step: isize,
+    col_step: isize,
+) {
+    let mut context = context_arc.lock();
+    let rows = rows_cols.rows.saturating_add_signed(row_step);
+    let cols = rows_cols.cols.saturating_add_signed(col_step);
+    context.resize(rows, cols);
+    rows_cols.rows = context.rows;
+    rows_cols.cols = context.cols;
+    *cursor.cursor_row = (*cursor.cursor_row).min(rows_cols.rows - 1);
+    *cursor.cursor_col = (*cursor.cursor_col).min(rows_cols.cols - 1);
+}
+
 pub fn clear_grid(
     context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
     rows: usize,
@@ -362,6 +754,7 @@ pub fn clear_grid(
     context.grid = (0..rows)
         .map(|_| (0..cols).map(|_| '.').collect())
         .collect();
+    context.index_grid();
 }
 
 pub fn copy(
@@ -404,6 +797,7 @@ pub fn paste(
     cursor_row: usize,
     cursor_col: usize,
     mode: &mut Mode,
+    offset: i32,
 ) {
     let mut clipboard = ClipboardContext::new().expect("Failed to get clipboard");
     let cells_to_paste: Vec<Vec<char>> = clipboard
@@ -413,26 +807,35 @@ pub fn paste(
         .map(|row| row.chars().filter(|c| !c.is_whitespace()).collect())
         .collect();
 
-    if let cells = cells_to_paste {
-        let mut _context = context_arc.lock();
-        let max_row_index = _context.grid.len() - 1;
-        let max_col_index = _context.grid[0].len() - 1;
+    let mut _context = context_arc.lock();
+    let max_row_index = _context.grid.len() - 1;
+    let max_col_index = _context.grid[0].len() - 1;
 
-        for (r, row) in cells.iter().enumerate() {
-            for (c, &value) in row.iter().enumerate() {
-                let target_row = cursor_row + r;
-                let target_col = cursor_col + c + 1;
+    for (r, row) in cells_to_paste.iter().enumerate() {
+        for (c, &value) in row.iter().enumerate() {
+            let target_row = cursor_row + r;
+            let target_col = cursor_col + c + 1;
 
-                // Only paste cells within the grid boundaries
-                if target_row <= max_row_index && target_col <= max_col_index {
-                    _context.grid[target_row][target_col] = value;
-                }
+            // Only paste cells within the grid boundaries
+            if target_row <= max_row_index && target_col <= max_col_index {
+                _context.write(target_row as i32, target_col as i32, transpose_cell(value, offset));
             }
         }
     }
     *mode = Mode::Normal;
 }
 
+// shifts a base-36 value cell by offset steps, wrapping around z; operator glyphs are left as they are
+pub fn transpose_cell(value: char, offset: i32) -> char {
+    match value {
+        '0'..='9' | 'a'..='z' => {
+            let (base_36, _) = char_to_base_36(value);
+            base_36_to_char((base_36 as i32 + offset).rem_euclid(36) as u8, false)
+        }
+        _ => value,
+    }
+}
+
 pub fn pause(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
     let mut context = context_arc.lock();
     if context.app_state == AppState::Running {
@@ -446,27 +849,14 @@ pub fn quit(
     context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
     terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
 ) {
-    let dir_path = Path::new("orca/sessions");
-    if !dir_path.exists() {
-        fs::create_dir_all(dir_path).expect("Unable to create directory");
-    }
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("orca/sessions/last_session")
-        .expect("Unable to save file");
-
-    let grid = { context_arc.lock().grid.clone() };
-
-    for row in grid {
-        let row_string: String = row.into_iter().collect();
-        file.write_all(row_string.as_bytes()).expect("Unable to write file");
-        file.write_all(b"\n").expect("Unable to write file");
-    }
+    let saved = context_arc.lock().save("last_session".to_string());
+    execute!(terminal.backend_mut(), DisableMouseCapture).unwrap();
     disable_raw_mode().unwrap();
     terminal.show_cursor().unwrap();
     terminal.clear().unwrap();
+    if let Err(err) = saved {
+        eprintln!("Unable to save the last session: {}", err);
+    }
     std::process::exit(0);
 }
 
@@ -476,7 +866,22 @@ pub fn change_midi_port(
     context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
 ) {
     let mut context = context_arc.lock();
-    context.midi_port += 1;
+    context.midi_port = context.midi_port.wrapping_add(1);
+}
+
+pub fn toggle_midi_clock(
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+) {
+    let mut context = context_arc.lock();
+    context.midi_clock = !context.midi_clock;
+}
+
+pub fn midi_panic(
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+) {
+    let mut context = context_arc.lock();
+    context.notes.clear();
+    context.midi_panic = true;
 }
 
 pub fn escape(mode: &mut Mode) {
@@ -490,12 +895,216 @@ pub fn escape(mode: &mut Mode) {
 
 pub fn tempo_up(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
     let mut context = context_arc.lock();
-    context.tempo += 1;
+    let tempo = context.tempo + 1;
+    context.set_tempo(tempo);
 }
 
 pub fn tempo_down(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
     let mut context = context_arc.lock();
     if context.tempo > 1 {
-        context.tempo -= 1;
+        let tempo = context.tempo - 1;
+        context.set_tempo(tempo);
+    }
+}
+
+pub fn volume_up(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
+    let mut context = context_arc.lock();
+    let volume = context.master_volume.value() + 0.05;
+    context.set_master_volume(volume);
+}
+
+pub fn volume_down(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
+    let mut context = context_arc.lock();
+    let volume = context.master_volume.value() - 0.05;
+    context.set_master_volume(volume);
+}
+
+// rescans the samples folder for the sampler thread and confirms how many files it found
+pub fn reload_samples(
+    context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>,
+    sample_reload_sender: &Sender<()>,
+) {
+    // the sampler thread confirms in the status line once the files are loaded
+    context_arc.lock().status_message = Some(("reloading samples".to_string(), Instant::now()));
+    let _ = sample_reload_sender.send(());
+}
+
+pub fn swing_up(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
+    let mut context = context_arc.lock();
+    let swing = context.swing.saturating_add(5);
+    context.set_swing(swing);
+}
+
+pub fn swing_down(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
+    let mut context = context_arc.lock();
+    let swing = context.swing.saturating_sub(5);
+    context.set_swing(swing);
+}
+
+pub fn divisions_up(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
+    let mut context = context_arc.lock();
+    let divisions = context.divisions + 1;
+    context.set_divisions(divisions);
+}
+
+pub fn divisions_down(context_arc: &Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Context>>) {
+    let mut context = context_arc.lock();
+    let divisions = context.divisions.saturating_sub(1);
+    context.set_divisions(divisions);
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    fn context_arc() -> Arc<Mutex<Context>> {
+        Arc::new(Mutex::new(Context::new(120, 4, 8, 8, "new", Some(0), "operator_config.txt")))
+    }
+
+    #[test]
+    fn changing_the_tempo_updates_tick_time() {
+        let context_arc = context_arc();
+        assert_eq!(context_arc.lock().tick_time, 125);
+        for _ in 0..30 {
+            tempo_down(&context_arc);
+        }
+        assert_eq!(context_arc.lock().tick_time, 166);
+        for _ in 0..60 {
+            tempo_up(&context_arc);
+        }
+        let context = context_arc.lock();
+        assert_eq!((context.tempo, context.tick_time), (150, 100));
+    }
+
+    #[test]
+    fn the_volume_clamps_to_0_and_1_and_reaches_the_audio_threads() {
+        let context_arc = context_arc();
+        // the synth and sampler hold clones of the shared value
+        let master_volume = context_arc.lock().master_volume.clone();
+        volume_down(&context_arc);
+        assert!((master_volume.value() - 0.95).abs() < 1e-9);
+        for _ in 0..30 {
+            volume_down(&context_arc);
+        }
+        assert_eq!(master_volume.value(), 0.0);
+        for _ in 0..30 {
+            volume_up(&context_arc);
+        }
+        assert_eq!(master_volume.value(), 1.0);
+
+        context_arc.lock().set_master_volume(-2.0);
+        assert_eq!(master_volume.value(), 0.0);
+    }
+
+    #[test]
+    fn transposing_shifts_values_and_leaves_operators_alone() {
+        let transposed = |row: &str, offset| row.chars().map(|value| transpose_cell(value, offset)).collect::<String>();
+        assert_eq!(transposed("1D4.:03Cz*", 1), "2D5.:14C0*");
+        assert_eq!(transposed("1D4.:03Cz*", -2), "zD2.:y1Cx*");
+    }
+
+    // the top left height x width corner of the grid, one string per row
+    fn corner(context_arc: &Arc<Mutex<Context>>, height: usize, width: usize) -> Vec<String> {
+        let context = context_arc.lock();
+        context.grid[..height].iter().map(|row| row[..width].iter().collect()).collect()
+    }
+
+    #[test]
+    fn typing_over_a_selection_fills_it() {
+        let context_arc = context_arc();
+        // dragged from the bottom right up to the top left
+        let mut mode = Mode::Select { start: (2, 3), end: (0, 1) };
+        let (mut cursor_row, mut cursor_col) = (0, 1);
+        input_char('C', &mut mode, &mut cursor_row, &mut cursor_col, &context_arc, &mut None);
+        assert_eq!(corner(&context_arc, 4, 5), [".CCC.", ".CCC.", ".CCC.", "....."]);
+        assert!(matches!(mode, Mode::Normal));
+
+        // outside a selection only the cursor cell is written
+        input_char('D', &mut mode, &mut cursor_row, &mut cursor_col, &context_arc, &mut None);
+        assert_eq!(corner(&context_arc, 1, 5), [".DCC."]);
+    }
+
+    #[test]
+    fn stepping_a_glyph_wraps_in_base_36() {
+        let context_arc = context_arc();
+        for (col, value) in "9zY*".chars().enumerate() {
+            context_arc.lock().write(0, col as i32, value);
+        }
+        for col in 0..4 {
+            step_glyph(&context_arc, 0, col, 1);
+        }
+        // the case is kept and operators are left alone
+        assert_eq!(corner(&context_arc, 1, 4), ["a0Z*"]);
+        step_glyph(&context_arc, 0, 1, -1);
+        assert_eq!(corner(&context_arc, 1, 4), ["azZ*"]);
+    }
+
+    fn write_rows(context_arc: &Arc<Mutex<Context>>, rows: &[&str]) {
+        let mut context = context_arc.lock();
+        for (row, line) in rows.iter().enumerate() {
+            for (col, value) in line.chars().enumerate() {
+                context.write(row as i32, col as i32, value);
+            }
+        }
+    }
+
+    #[test]
+    fn flipping_a_2x3_block_mirrors_it() {
+        let context_arc = context_arc();
+        write_rows(&context_arc, &["1E3", "4N6"]);
+        let mode = Mode::Select { start: (0, 0), end: (1, 2) };
+
+        flip_selection(&mode, &context_arc, false);
+        assert_eq!(corner(&context_arc, 3, 4), ["3W1.", "6N4.", "...."]);
+        flip_selection(&mode, &context_arc, true);
+        assert_eq!(corner(&context_arc, 3, 4), ["6S4.", "3W1.", "...."]);
+    }
+
+    #[test]
+    fn rotating_a_2x3_block_gives_a_3x2_block() {
+        let context_arc = context_arc();
+        write_rows(&context_arc, &["123", "456"]);
+        let mut mode = Mode::Select { start: (0, 0), end: (1, 2) };
+
+        rotate_selection(&mut mode, &context_arc);
+        assert_eq!(corner(&context_arc, 4, 4), ["41..", "52..", "63..", "...."]);
+        assert!(matches!(mode, Mode::Select { start: (0, 0), end: (2, 1) }));
+    }
+
+    #[test]
+    fn divisions_never_reach_0() {
+        let context_arc = context_arc();
+        divisions_up(&context_arc);
+        assert_eq!(context_arc.lock().tick_time, 100);
+        for _ in 0..8 {
+            divisions_down(&context_arc);
+        }
+        {
+            let context = context_arc.lock();
+            assert_eq!((context.divisions, context.tick_time), (1, 500));
+        }
+
+        let mut context = context_arc.lock();
+        context.set_divisions(0);
+        context.set_tempo(0);
+        assert_eq!((context.tempo, context.divisions, context.tick_time), (1, 1, 60000));
+    }
+
+    #[test]
+    fn shrinking_past_the_cursor_clamps_it_and_keeps_the_top_left() {
+        let context_arc = context_arc();
+        write_rows(&context_arc, &["D8..", ".*.1", "..C4"]);
+        let mut rows_cols = RowsCols { rows: 8, cols: 8 };
+        let (mut cursor_row, mut cursor_col) = (6, 7);
+        let mut cursor = Cursor { cursor_row: &mut cursor_row, cursor_col: &mut cursor_col };
+
+        resize_grid(&context_arc, &mut rows_cols, &mut cursor, -6, -5);
+        assert_eq!((rows_cols.rows, rows_cols.cols), (2, 3));
+        assert_eq!((cursor_row, cursor_col), (1, 2));
+        assert_eq!(corner(&context_arc, 2, 3), ["D8.", ".*."]);
+        let context = context_arc.lock();
+        assert_eq!((context.rows, context.cols, context.grid.len(), context.grid[1].len()), (2, 3, 2, 3));
     }
 }
@@ -0,0 +1,213 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Jump,
+    Replace,
+    TempoUp,
+    TempoDown,
+    DivisionsUp,
+    DivisionsDown,
+    Quit,
+    Copy,
+    Paste,
+    PasteUp,
+    PasteDown,
+    InsertRow,
+    DeleteRow,
+    InsertColumn,
+    DeleteColumn,
+    FlipHorizontal,
+    FlipVertical,
+    Rotate,
+    Variables,
+    Help,
+    Clear,
+    Pause,
+    MidiPort,
+    MidiClock,
+    ReloadSamples,
+    VelocityCurve,
+    Metronome,
+    Export,
+    Panic,
+    SwingUp,
+    SwingDown,
+    VolumeUp,
+    VolumeDown,
+    RemoveRow,
+    AddRow,
+    RemoveColumn,
+    AddColumn,
+    StepUp,
+    StepDown,
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "Jump" => Some(Action::Jump),
+            "Replace" => Some(Action::Replace),
+            "TempoUp" => Some(Action::TempoUp),
+            "TempoDown" => Some(Action::TempoDown),
+            "DivisionsUp" => Some(Action::DivisionsUp),
+            "DivisionsDown" => Some(Action::DivisionsDown),
+            "Quit" => Some(Action::Quit),
+            "Copy" => Some(Action::Copy),
+            "Paste" => Some(Action::Paste),
+            "PasteUp" => Some(Action::PasteUp),
+            "PasteDown" => Some(Action::PasteDown),
+            "InsertRow" => Some(Action::InsertRow),
+            "DeleteRow" => Some(Action::DeleteRow),
+            "InsertColumn" => Some(Action::InsertColumn),
+            "DeleteColumn" => Some(Action::DeleteColumn),
+            "FlipHorizontal" => Some(Action::FlipHorizontal),
+            "FlipVertical" => Some(Action::FlipVertical),
+            "Rotate" => Some(Action::Rotate),
+            "Variables" => Some(Action::Variables),
+            "Help" => Some(Action::Help),
+            "Clear" => Some(Action::Clear),
+            "Pause" => Some(Action::Pause),
+            "MidiPort" => Some(Action::MidiPort),
+            "MidiClock" => Some(Action::MidiClock),
+            "ReloadSamples" => Some(Action::ReloadSamples),
+            "VelocityCurve" => Some(Action::VelocityCurve),
+            "Metronome" => Some(Action::Metronome),
+            "Export" => Some(Action::Export),
+            "Panic" => Some(Action::Panic),
+            "SwingUp" => Some(Action::SwingUp),
+            "SwingDown" => Some(Action::SwingDown),
+            "VolumeUp" => Some(Action::VolumeUp),
+            "VolumeDown" => Some(Action::VolumeDown),
+            "RemoveRow" => Some(Action::RemoveRow),
+            "AddRow" => Some(Action::AddRow),
+            "RemoveColumn" => Some(Action::RemoveColumn),
+            "AddColumn" => Some(Action::AddColumn),
+            "StepUp" => Some(Action::StepUp),
+            "StepDown" => Some(Action::StepDown),
+            _ => None,
+        }
+    }
+}
+
+// maps keys to actions, read from keybindings.txt with one `key Action` per line;
+// keys are a character, space, up, down, left or right, optionally prefixed with ctrl-, alt- and shift-
+pub struct Keybindings {
+    actions: HashMap<(KeyCode, KeyModifiers), Action>,
+    // lines of the file that didn't parse, reported once the UI is up
+    pub ignored: Vec<String>,
+}
+
+impl Keybindings {
+    pub fn load(filename: &str) -> Keybindings {
+        // the keybindings.txt shipped with the repo is the default table, built in so it also applies
+        // without the file and the two can't drift apart
+        let default_keybindings = include_str!("../keybindings.txt");
+        let mut keybindings = Keybindings::parse(default_keybindings);
+        if let Ok(contents) = read_to_string(filename) {
+            // actions the file binds lose their default keys, the rest keep them
+            let custom = Keybindings::parse(&contents);
+            keybindings.actions.retain(|_, action| !custom.actions.values().any(|custom_action| custom_action == action));
+            keybindings.actions.extend(custom.actions);
+            keybindings.ignored = custom.ignored;
+        }
+        keybindings
+    }
+
+    pub fn parse(contents: &str) -> Keybindings {
+        let mut keybindings = Keybindings {
+            actions: HashMap::new(),
+            ignored: Vec::new(),
+        };
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let binding = line
+                .split_once(' ')
+                .and_then(|(key, action)| Some((parse_key(key)?, Action::from_name(action.trim())?)));
+            match binding {
+                Some((key, action)) => {
+                    keybindings.actions.insert(key, action);
+                }
+                None => keybindings.ignored.push(line.to_string()),
+            }
+        }
+        keybindings
+    }
+
+    // shift is dropped for characters since it already shows in their case
+    pub fn action(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        let modifiers = match code {
+            KeyCode::Char(_) => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        self.actions.get(&(code, modifiers)).copied()
+    }
+}
+
+fn parse_key(key: &str) -> Option<(KeyCode, KeyModifiers)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = key;
+    // a lone '-' is a key, not a modifier separator
+    while let Some((modifier, tail)) = rest.split_once('-').filter(|(_, tail)| !tail.is_empty()) {
+        modifiers |= match modifier {
+            "ctrl" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => break,
+        };
+        rest = tail;
+    }
+    let code = match rest {
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    let modifiers = match code {
+        KeyCode::Char(_) => modifiers - KeyModifiers::SHIFT,
+        _ => modifiers,
+    };
+    Some((code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_table_binds_every_action_once() {
+        let defaults = Keybindings::parse(include_str!("../keybindings.txt"));
+        assert!(defaults.ignored.is_empty(), "{:?}", defaults.ignored);
+        let mut actions: Vec<Action> = defaults.actions.values().copied().collect();
+        let bound = actions.len();
+        actions.sort_by_key(|action| format!("{:?}", action));
+        actions.dedup();
+        assert_eq!(actions.len(), bound);
+        assert_eq!(bound, 39);
+    }
+
+    #[test]
+    fn parsed_keys_carry_their_modifiers() {
+        let keybindings = Keybindings::parse("alt-shift-up RemoveRow\nctrl-c Copy\n- TempoDown\nalt-V PasteDown");
+        assert_eq!(keybindings.action(KeyCode::Up, KeyModifiers::ALT | KeyModifiers::SHIFT), Some(Action::RemoveRow));
+        assert_eq!(keybindings.action(KeyCode::Char('c'), KeyModifiers::CONTROL), Some(Action::Copy));
+        assert_eq!(keybindings.action(KeyCode::Char('-'), KeyModifiers::NONE), Some(Action::TempoDown));
+        assert_eq!(keybindings.action(KeyCode::Char('V'), KeyModifiers::ALT | KeyModifiers::SHIFT), Some(Action::PasteDown));
+        assert_eq!(keybindings.action(KeyCode::Up, KeyModifiers::ALT), None);
+    }
+
+    #[test]
+    fn lines_that_dont_parse_are_reported() {
+        let keybindings = Keybindings::parse("ctrl-c Copy\nctrl-c Cpy\nhyper-x Quit");
+        assert_eq!(keybindings.ignored, ["ctrl-c Cpy", "hyper-x Quit"]);
+    }
+}
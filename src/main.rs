@@ -1,27 +1,39 @@
 use std::{
+    collections::HashMap,
     sync::atomic::{AtomicBool, Ordering},
     sync::Arc,
-    time::Duration,
+    thread,
+    time::{Duration, Instant},
 };
 use crate::{
-    context::{Context, Mode},
-    midi::{run_midi, run_midi_cc},
+    context::{AppState, Context, Mode, Prompt},
+    keybindings::Keybindings,
+    midi::{run_midi, run_midi_in},
     note_events::{run_notes, Note},
-    sampler::sampler_out,
-    synth::synth_out,
+    operators::read_operator_config,
+    sampler::{sampler_out, DEFAULT_SAMPLER_VOICES},
+    synth::{synth_out, DEFAULT_VOICES},
+    theme::Theme,
+    ui::Viewport,
 };
 use crossbeam::channel::{unbounded, Sender};
-use crossterm::{event::poll, terminal::enable_raw_mode};
+use crossterm::{
+    event::{poll, EnableMouseCapture},
+    execute,
+    terminal::enable_raw_mode,
+};
 use parking_lot::Mutex;
 use ratatui::{backend::CrosstermBackend, Terminal};
 
 mod context;
 mod event_handling;
+mod keybindings;
 mod midi;
 mod note_events;
 mod operators;
 mod sampler;
 mod synth;
+mod theme;
 mod ui;
 mod utils;
 
@@ -30,6 +42,9 @@ pub struct NoteSenders {
     sampler_note_sender: Sender<Vec<Note>>,
     midi_cc_sender: Sender<Vec<Note>>,
     synth_note_sender: Sender<Vec<Note>>,
+    pitch_bend_sender: Sender<Vec<Note>>,
+    // the clock pulses of one tick and how long that tick lasts, so they are spread over it
+    clock_pulse_sender: Sender<(u64, Duration)>,
 }
 
 pub struct RowsCols {
@@ -43,14 +58,17 @@ pub struct Cursor<'a> {
 }
 
 fn main() {
-    // get arguments
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    // prepare terminal
-    let stdout = std::io::stdout();
-    enable_raw_mode().unwrap();
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).unwrap();
-    terminal.clear().unwrap();
+    // get arguments: the session, rows and cols by position, everything else as --flags that can go anywhere
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let headless = args.iter().any(|arg| arg == "--headless");
+    let paused = args.iter().any(|arg| arg == "--paused");
+    args.retain(|arg| arg != "--headless" && arg != "--paused");
+    let flags = take_flags(&mut args);
+    // headless has no space bar, so only a MIDI clock start can end a paused start
+    if headless && paused && flags.get("clock").map(String::as_str) != Some("follow") {
+        eprintln!("--paused with --headless needs --clock follow, or playback would never start");
+        std::process::exit(2);
+    }
 
     // prepare context
     let mut cursor = Cursor {
@@ -60,17 +78,29 @@ fn main() {
     let mut selected_cells: Option<Vec<Vec<char>>> = None;
     let mut mode = Mode::Normal;
     let rows_cols = RowsCols {
-        rows: args.get(1).unwrap_or(&"50".to_string()).parse().unwrap(),
-        cols: args.get(2).unwrap_or(&"150".to_string()).parse().unwrap(),
+        rows: flags.get("rows").and_then(|rows| rows.parse().ok()).unwrap_or_else(|| args.get(1).unwrap_or(&"50".to_string()).parse().unwrap()),
+        cols: flags.get("cols").and_then(|cols| cols.parse().ok()).unwrap_or_else(|| args.get(2).unwrap_or(&"150".to_string()).parse().unwrap()),
     };
-    let new_or_last: String = args.get(0).unwrap_or(&"new".to_string()).parse().unwrap();
+    let operator_config = flags.get("config").map_or("operator_config.txt", String::as_str);
+    let new_or_last: String = args.first().unwrap_or(&"new".to_string()).parse().unwrap();
+
+    let seed: Option<u64> = flags.get("seed").and_then(|seed| seed.parse().ok());
 
-    let context = Context::new(110, 4, rows_cols.rows, rows_cols.cols, &new_or_last);
+    let mut context = Context::new(110, 4, rows_cols.rows, rows_cols.cols, &new_or_last, seed, operator_config);
+    apply_flags(&mut context, &flags, paused);
+    // loaded sessions and imported ORCA files can be larger or smaller than asked for
+    let mut rows_cols = RowsCols {
+        rows: context.rows,
+        cols: context.cols,
+    };
     let should_redraw = Arc::new(AtomicBool::new(true));
     let should_redraw_notes = Arc::clone(&should_redraw);
+    let master_volume = context.master_volume.clone();
+    let midi_port = context.midi_port as usize;
     let context_arc = Arc::new(Mutex::new(context));
     let notes_context_arc = Arc::clone(&context_arc);
     let midi_context_arc = Arc::clone(&context_arc);
+    let midi_in_context_arc = Arc::clone(&context_arc);
 
     // prepare channels
     let (midi_note_sender, midi_note_receiver) = unbounded();
@@ -78,13 +108,29 @@ fn main() {
     let (midi_port_sender, midi_port_receiver) = unbounded();
     let (sampler_note_sender, sampler_note_receiver) = unbounded();
     let (synth_note_sender, synth_note_receiver) = unbounded();
+    let (pitch_bend_sender, pitch_bend_receiver) = unbounded();
+    let (clock_pulse_sender, clock_pulse_receiver) = unbounded();
+    let (sample_reload_sender, sample_reload_receiver) = unbounded();
     let mut show_popup = true;
+    let mut show_variables = false;
+    let mut viewport = Viewport::default();
+    let mut prompt: Option<Prompt> = None;
+    let theme = Theme::load("orca/theme.toml");
+    // halted cells are styled under whatever glyph the config gives Halt
+    let halt_glyph = read_operator_config(operator_config).get("Halt").copied();
+    let keybindings = Keybindings::load("keybindings.txt");
+    if !keybindings.ignored.is_empty() {
+        let message = format!("keybindings.txt: ignored {}", keybindings.ignored.join(", "));
+        context_arc.lock().status_message = Some((message, Instant::now()));
+    }
 
     let note_senders = NoteSenders {
         midi_note_sender,
         sampler_note_sender,
         midi_cc_sender,
         synth_note_sender,
+        pitch_bend_sender,
+        clock_pulse_sender,
     };
 
     // run note events
@@ -96,19 +142,45 @@ fn main() {
     );
 
     // run synth thread
-    synth_out(synth_note_receiver);
+    // the oldest synth voice is stolen once this many are sounding
+    let voice_limit = flags.get("voices").and_then(|voices| voices.parse().ok()).unwrap_or(DEFAULT_VOICES).max(1);
+    synth_out(synth_note_receiver, master_volume.clone(), voice_limit);
 
     // run sampler thread
-    sampler_out(sampler_note_receiver);
+    let sampler_voice_limit =
+        flags.get("sampler-voices").and_then(|voices| voices.parse().ok()).unwrap_or(DEFAULT_SAMPLER_VOICES).max(1);
+    sampler_out(
+        sampler_note_receiver,
+        sample_reload_receiver,
+        master_volume,
+        sampler_voice_limit,
+        Arc::clone(&context_arc),
+    );
 
     // run MIDI thread
     run_midi(
         midi_note_receiver,
         midi_port_receiver,
+        pitch_bend_receiver,
+        midi_cc_receiver,
+        clock_pulse_receiver,
         midi_context_arc,
+        midi_port,
     );
 
-    run_midi_cc(midi_cc_receiver);
+    run_midi_in(midi_in_context_arc);
+
+    if headless {
+        run_headless(&context_arc);
+    }
+
+    // prepare terminal
+    let mut stdout = std::io::stdout();
+    enable_raw_mode().unwrap();
+    execute!(stdout, EnableMouseCapture).unwrap();
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend).unwrap();
+    terminal.clear().unwrap();
 
     // run TUI
     loop {
@@ -120,6 +192,11 @@ fn main() {
                 &should_redraw,
                 &context_arc,
                 show_popup,
+                show_variables,
+                &mut viewport,
+                &theme,
+                halt_glyph,
+                prompt.as_ref(),
             );
         }
 
@@ -132,8 +209,122 @@ fn main() {
                 &mut selected_cells,
                 &mut cursor,
                 &mut show_popup,
-                &rows_cols,
+                &mut show_variables,
+                &mut rows_cols,
+                &sample_reload_sender,
+                &viewport,
+                &mut prompt,
+                &keybindings,
             );
         }
     }
 }
+
+// plays the grid without the terminal until ctrl-c, then stops ticking and silences MIDI before exiting
+fn run_headless(context_arc: &Arc<Mutex<Context>>) -> ! {
+    let (stop_sender, stop_receiver) = unbounded();
+    ctrlc::set_handler(move || {
+        let _ = stop_sender.send(());
+    })
+    .expect("Failed to set the ctrl-c handler");
+    stop_receiver.recv().expect("Failed to wait for ctrl-c");
+
+    {
+        let mut context = context_arc.lock();
+        context.app_state = AppState::Paused;
+        context.notes.clear();
+        context.midi_panic = true;
+    }
+    // the MIDI thread clears the flag once all notes off went out; without a MIDI port it never does
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while context_arc.lock().midi_panic && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    std::process::exit(0);
+}
+
+// --name value flags can go anywhere and are taken out, so the positional arguments keep their places
+// flags win over the settings saved with the session
+fn apply_flags(context: &mut Context, flags: &HashMap<String, String>, paused: bool) {
+    if let Some(tempo) = flags.get("tempo").and_then(|tempo| tempo.parse().ok()) {
+        context.set_tempo(tempo);
+    }
+    if let Some(divisions) = flags.get("divisions").and_then(|divisions| divisions.parse().ok()) {
+        context.set_divisions(divisions);
+    }
+    if let Some(midi_port) = flags.get("port").and_then(|port| port.parse().ok()) {
+        context.midi_port = midi_port;
+    }
+    // space starts it when the set is ready
+    if paused {
+        context.app_state = AppState::Paused;
+    }
+    // "send" drives external gear with MIDI clock, "follow" ticks on incoming clock
+    match flags.get("clock").map(String::as_str) {
+        Some("send") => context.midi_clock = true,
+        Some("follow") => context.clock_follow = true,
+        _ => {}
+    }
+    // incoming notes bang twelve cells starting at this row,col, one per pitch class
+    if let Some((row, col)) = flags.get("midi-bang").and_then(|cell| cell.split_once(',')) {
+        if let (Ok(row), Ok(col)) = (row.trim().parse(), col.trim().parse()) {
+            context.midi_bang_cell = Some((row, col));
+        }
+    }
+    // the grid is written to orca/sessions/autosave every this many ticks, 0 turns it off
+    if let Some(autosave_ticks) = flags.get("autosave").and_then(|ticks| ticks.parse().ok()) {
+        context.autosave_ticks = autosave_ticks;
+    }
+    if let Some(grid_spacing) = flags.get("grid-spacing").and_then(|spacing| spacing.parse().ok()) {
+        context.grid_spacing = grid_spacing;
+    }
+}
+
+fn take_flags(args: &mut Vec<String>) -> HashMap<String, String> {
+    let mut flags = HashMap::new();
+    let mut positional = Vec::new();
+    let mut args_iter = args.drain(..);
+    while let Some(arg) = args_iter.next() {
+        match arg.strip_prefix("--") {
+            Some(name) => {
+                if let Some(value) = args_iter.next() {
+                    flags.insert(name.to_string(), value);
+                }
+            }
+            None => positional.push(arg),
+        }
+    }
+    drop(args_iter);
+    *args = positional;
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_come_out_and_positional_arguments_keep_their_places() {
+        let mut args: Vec<String> = ["last", "--voices", "8", "40", "--midi-bang", "4,0", "120"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let flags = take_flags(&mut args);
+        assert_eq!(args, ["last", "40", "120"]);
+        assert_eq!(flags.get("voices").map(String::as_str), Some("8"));
+        assert_eq!(flags.get("midi-bang").map(String::as_str), Some("4,0"));
+    }
+
+    #[test]
+    fn the_paused_flag_starts_the_context_paused() {
+        let flags = HashMap::from([("tempo".to_string(), "140".to_string())]);
+        let mut context = Context::new(110, 4, 4, 4, "new", Some(0), "operator_config.txt");
+        apply_flags(&mut context, &flags, false);
+        assert!(context.app_state == AppState::Running);
+        assert_eq!(context.tempo, 140);
+
+        let mut context = Context::new(110, 4, 4, 4, "new", Some(0), "operator_config.txt");
+        apply_flags(&mut context, &flags, true);
+        assert!(context.app_state == AppState::Paused);
+    }
+}
@@ -1,35 +1,86 @@
 use std::{
+    collections::{hash_map::Entry, HashMap},
     sync::Arc,
     thread::{self},
+    time::{Duration, Instant},
 };
 
-use crossbeam::channel::Receiver;
-use midir::MidiOutput;
+use crossbeam::channel::{select, Receiver};
+use midir::{MidiInput, MidiOutput, MidiOutputConnection};
 use parking_lot::Mutex;
 use crate::{
     context::{AppState, Context},
     note_events::Note,
 };
 
-pub const _NOTE_ON_MESSAGE: u8 = 0x90;
+pub const NOTE_ON_MESSAGE: u8 = 0x90;
 pub const NOTE_OFF_MESSAGE: u8 = 0x80;
 pub const MIDI_CHANNEL_COUNT: u8 = 16;
 pub const MIDI_NOTE_COUNT: u8 = 128;
+pub const CONTROL_CHANGE_MESSAGE: u8 = 0xB0;
+pub const PITCH_BEND_MESSAGE: u8 = 0xE0;
+pub const PITCH_BEND_CENTER: u16 = 8192;
+pub const CLOCK_MESSAGE: u8 = 0xF8;
+pub const CLOCK_START_MESSAGE: u8 = 0xFA;
+pub const CLOCK_STOP_MESSAGE: u8 = 0xFC;
+pub const CLOCK_PULSES_PER_BEAT: u64 = 24;
+// note-ons kept for the midi in operator between two ticks
+pub const MAX_INCOMING_NOTES: usize = 128;
 
+// response of outgoing note and CC velocities, applied to the 0-127 value right before it is sent
+#[derive(PartialEq, Copy, Clone)]
+pub enum VelocityCurve {
+    Linear,
+    Exponential,
+    Logarithmic,
+}
+
+impl VelocityCurve {
+    pub fn apply(self, velocity: u8) -> u8 {
+        let normalized = velocity.min(127) as f32 / 127.0;
+        let curved = match self {
+            VelocityCurve::Linear => normalized,
+            VelocityCurve::Exponential => (2.0_f32.powf(4.0 * normalized) - 1.0) / 15.0,
+            VelocityCurve::Logarithmic => (1.0 + 15.0 * normalized).log2() / 4.0,
+        };
+        (curved * 127.0).round() as u8
+    }
+
+    pub fn next(self) -> VelocityCurve {
+        match self {
+            VelocityCurve::Linear => VelocityCurve::Exponential,
+            VelocityCurve::Exponential => VelocityCurve::Logarithmic,
+            VelocityCurve::Logarithmic => VelocityCurve::Linear,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VelocityCurve::Linear => "lin",
+            VelocityCurve::Exponential => "exp",
+            VelocityCurve::Logarithmic => "log",
+        }
+    }
+}
 
 pub fn run_midi(
     midi_note_receiver: Receiver<Vec<Note>>,
     midi_port_receiver: Receiver<usize>,
+    pitch_bend_receiver: Receiver<Vec<Note>>,
+    midi_cc_receiver: Receiver<Vec<Note>>,
+    clock_pulse_receiver: Receiver<(u64, Duration)>,
     midi_context_arc: Arc<Mutex<Context>>,
+    midi_port: usize,
 ) {
     thread::spawn(move || {
 
-        // prepare MIDI
+        // prepare MIDI, starting on the port picked with --port so CC, pitch bend and the status line
+        // use it before the first notes arrive
         let mut midi_out = MidiOutput::new("rust-orca").unwrap();
         let out_ports = midi_out.ports();
-        let mut default_midi_port = 0;
+        let mut default_midi_port = midi_port;
         let out_port = out_ports
-            .get(default_midi_port)
+            .get(default_midi_port % out_ports.len().max(1))
             .ok_or("No MIDI output ports available")
             .unwrap();
 
@@ -44,100 +95,267 @@ pub fn run_midi(
         let mut midi_conn = midi_out.connect(out_port, "rust-orca-conn").unwrap();
 
         // clear all existing midi notes on start
-        for channel in 0..MIDI_CHANNEL_COUNT {
-            for note in 0..MIDI_NOTE_COUNT {
-                let note_off_message = NOTE_OFF_MESSAGE + channel;
-                midi_conn.send(&[note_off_message, note, 0]).unwrap();
-            }
-        }
+        all_notes_off(&mut midi_conn);
+
+        // notes addressed to other ports get their own connections, opened on first use
+        let mut port_count = out_ports.len();
+        let mut other_conns: HashMap<usize, MidiOutputConnection> = HashMap::new();
 
         // run the main loop
+        let mut midi_clock = false;
+        let mut app_state = AppState::Running;
+        // pulses of the current tick still to send, spread evenly over it
+        let mut pending_pulses = 0;
+        let mut pulse_interval = Duration::ZERO;
+        let mut next_pulse = Instant::now();
         loop {
-            // set the new midi port if changed
-            let requested_midi_port = midi_port_receiver.recv().unwrap();
-            if requested_midi_port != default_midi_port {
-                default_midi_port = requested_midi_port;
-                midi_out = midi_conn.close();
-                let out_ports = midi_out.ports();
-                let out_port = out_ports.get(requested_midi_port % out_ports.len())
-                    .ok_or("No MIDI output ports available")
-                    .unwrap();
-                let midi_port_name = midi_out.port_name(out_port).unwrap();
-                let mut context = midi_context_arc.lock();
-                context.midi_port_name = midi_port_name.clone();
-                midi_conn = midi_out.connect(out_port, "rust-orca-conn").unwrap();
+            // send start/stop when the clock is toggled or playback is paused and resumed
+            let (requested_app_state, requested_midi_clock, midi_panic, velocity_curve) = {
+                let context = midi_context_arc.lock();
+                (context.app_state, context.midi_clock, context.midi_panic, context.velocity_curve)
+            };
+
+            // silence everything when the panic key was pressed, and only then clear the flag so
+            // headless shutdown can wait for it
+            if midi_panic {
+                all_notes_off(&mut midi_conn);
+                for conn in other_conns.values_mut() {
+                    all_notes_off(conn);
+                }
+                midi_context_arc.lock().midi_panic = false;
             }
 
-            // process notes
-            let mut notes = midi_note_receiver.recv().unwrap();
-            for note in notes.iter_mut() {
-                if note.started && note.duration == 0 {
-                    note.stop(&mut midi_conn);
-                } else if !note.started {
-                    note.stop(&mut midi_conn);
-                    note.start(&mut midi_conn);
+            if requested_midi_clock != midi_clock || requested_app_state != app_state {
+                let was_playing = midi_clock && app_state == AppState::Running;
+                let is_playing = requested_midi_clock && requested_app_state == AppState::Running;
+                if is_playing && !was_playing {
+                    midi_conn.send(&[CLOCK_START_MESSAGE]).unwrap();
+                } else if was_playing && !is_playing {
+                    midi_conn.send(&[CLOCK_STOP_MESSAGE]).unwrap();
+                    pending_pulses = 0;
                 }
+
+                // clear all midi notes on shutdown
+                if requested_app_state == AppState::Shutdown {
+                    all_notes_off(&mut midi_conn);
+                }
+                midi_clock = requested_midi_clock;
+                app_state = requested_app_state;
             }
 
-            // clear all midi notes on shutdown
-            let is_shutdown = { midi_context_arc.lock().app_state };
-            if is_shutdown == AppState::Shutdown {
-                for channel in 0..MIDI_CHANNEL_COUNT {
-                    for note in 0..MIDI_NOTE_COUNT {
-                        let note_off_message = NOTE_OFF_MESSAGE + channel;
-                        midi_conn.send(&[note_off_message, note, 0]).unwrap();
+            // wait for notes, a port change or the next clock pulse
+            let timeout = if pending_pulses > 0 {
+                next_pulse.saturating_duration_since(Instant::now())
+            } else {
+                Duration::from_millis(10)
+            };
+            select! {
+                recv(midi_port_receiver) -> requested_midi_port => {
+                    // set the new midi port if changed
+                    let requested_midi_port = requested_midi_port.unwrap();
+                    if requested_midi_port != default_midi_port {
+                        default_midi_port = requested_midi_port;
+                        midi_out = midi_conn.close();
+                        let out_ports = midi_out.ports();
+                        port_count = out_ports.len();
+                        other_conns.remove(&(requested_midi_port % port_count));
+                        let out_port = out_ports.get(requested_midi_port % port_count)
+                            .ok_or("No MIDI output ports available")
+                            .unwrap();
+                        let midi_port_name = midi_out.port_name(out_port).unwrap();
+                        let mut context = midi_context_arc.lock();
+                        context.midi_port_name = midi_port_name.clone();
+                        midi_conn = midi_out.connect(out_port, "rust-orca-conn").unwrap();
+                    }
+                }
+                recv(midi_note_receiver) -> notes => {
+                    // process notes
+                    let mut notes = notes.unwrap();
+                    for note in notes.iter_mut() {
+                        let conn = match note_output(note.port, default_midi_port, port_count) {
+                            None => &mut midi_conn,
+                            Some(port) => match other_conns.entry(port) {
+                                Entry::Occupied(entry) => entry.into_mut(),
+                                Entry::Vacant(entry) => match connect_port(port) {
+                                    Some(conn) => entry.insert(conn),
+                                    None => continue,
+                                },
+                            },
+                        };
+                        if note.started && note.duration == 0 {
+                            note.stop(conn);
+                        } else if !note.started {
+                            note.velocity = velocity_curve.apply(note.velocity);
+                            note.stop(conn);
+                            note.start(conn);
+                        }
+                    }
+                }
+                recv(pitch_bend_receiver) -> pitch_bends => {
+                    for note in pitch_bends.unwrap() {
+                        midi_conn.send(&pitch_bend_message(&note)).unwrap();
                     }
                 }
+                recv(midi_cc_receiver) -> midi_cc => {
+                    for note in midi_cc.unwrap().iter().filter(|note| !note.started) {
+                        midi_conn.send(&control_change_message(note, velocity_curve)).unwrap();
+                    }
+                }
+                recv(clock_pulse_receiver) -> tick_pulses => {
+                    // a new tick starts: anything left of the last one is sent now so no pulse is lost,
+                    // then this tick's pulses start on its boundary
+                    let (pulses, tick_duration) = tick_pulses.unwrap();
+                    if midi_clock && app_state == AppState::Running {
+                        for _ in 0..pending_pulses {
+                            midi_conn.send(&[CLOCK_MESSAGE]).unwrap();
+                        }
+                        pending_pulses = pulses;
+                        pulse_interval = tick_duration / pulses.max(1) as u32;
+                        next_pulse = Instant::now();
+                    }
+                }
+                default(timeout) => {}
+            }
+
+            // send the clock pulses that are due
+            while pending_pulses > 0 && Instant::now() >= next_pulse {
+                midi_conn.send(&[CLOCK_MESSAGE]).unwrap();
+                pending_pulses -= 1;
+                next_pulse += pulse_interval;
             }
         }
     });
 }
 
-pub fn run_midi_cc(midi_cc_receiver: Receiver<Vec<Note>>) {
-    let midi_out = MidiOutput::new("rust-orca").unwrap();
+// the output a note is sent through: None for the selected port's own connection, or the index of another
+// port, wrapping around the ports there are
+fn note_output(note_port: u8, selected_port: usize, port_count: usize) -> Option<usize> {
+    let port = note_port as usize % port_count.max(1);
+    (port != selected_port % port_count.max(1)).then_some(port)
+}
+
+// the bytes of a pitch bend note: status with its channel, then the low and high seven bits
+pub fn pitch_bend_message(note: &Note) -> [u8; 3] {
+    [PITCH_BEND_MESSAGE + note.channel, note.note_number, note.velocity]
+}
+
+// the status byte already carries the channel, the controller is in degree
+pub fn control_change_message(note: &Note, velocity_curve: VelocityCurve) -> [u8; 3] {
+    [note.channel, note.degree, velocity_curve.apply(scale_velocity(note.velocity))]
+}
+
+// opens an extra connection to the output port at the given index
+fn connect_port(port: usize) -> Option<MidiOutputConnection> {
+    let midi_out = MidiOutput::new("rust-orca").ok()?;
     let out_ports = midi_out.ports();
-    let out_port = out_ports
-        .get(0)
-        .ok_or("No MIDI output ports available")
-        .unwrap();
-    let mut conn = midi_out.connect(out_port, "rust-orca-conn").unwrap();
+    let out_port = out_ports.get(port)?;
+    midi_out.connect(out_port, "rust-orca-conn").ok()
+}
+
+// sends a note off for every note on every channel
+pub fn all_notes_off(conn: &mut MidiOutputConnection) {
+    for channel in 0..MIDI_CHANNEL_COUNT {
+        for note in 0..MIDI_NOTE_COUNT {
+            let note_off_message = NOTE_OFF_MESSAGE + channel;
+            conn.send(&[note_off_message, note, 0]).unwrap();
+        }
+    }
+}
 
+pub fn run_midi_in(midi_context_arc: Arc<Mutex<Context>>) {
     thread::spawn(move || {
+        let midi_in = MidiInput::new("rust-orca-in").unwrap();
+        let in_ports = midi_in.ports();
+
+        // midi input is optional, so there is nothing to do without an input port
+        let Some(in_port) = in_ports.first() else {
+            return;
+        };
+
+        // collect note-ons for the midi in operator, which reads and clears them every tick
+        let _midi_in_conn = midi_in
+            .connect(
+                in_port,
+                "rust-orca-in-conn",
+                move |_, message, _| match *message {
+                    [status, note, velocity] if status & 0xF0 == NOTE_ON_MESSAGE && velocity > 0 => {
+                        let mut context = midi_context_arc.lock();
+                        // a tick clears these, so while paused or waiting on external clock they are
+                        // dropped instead of piling up
+                        if context.app_state == AppState::Running && context.incoming.len() < MAX_INCOMING_NOTES {
+                            context.incoming.push((status & 0x0F, note, velocity));
+                        }
+                        if context.midi_bang_cell.is_some() {
+                            context.bang_notes.push(note);
+                            context.held_notes.insert(note);
+                        }
+                    }
+                    [status, note, _] if status & 0xF0 == NOTE_OFF_MESSAGE || status & 0xF0 == NOTE_ON_MESSAGE => {
+                        midi_context_arc.lock().held_notes.remove(&note);
+                    }
+                    // clock pulses and transport only matter when following external clock
+                    [status] => {
+                        let mut context = midi_context_arc.lock();
+                        if context.clock_follow {
+                            match status {
+                                // pulses that arrive while paused would all play at once on resume
+                                CLOCK_MESSAGE if context.app_state == AppState::Running => context.clock_pulses += 1,
+                                CLOCK_START_MESSAGE => {
+                                    context.clock_pulses = 0;
+                                    context.app_state = AppState::Running;
+                                }
+                                CLOCK_STOP_MESSAGE => context.app_state = AppState::Paused,
+                                _ => {}
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                (),
+            )
+            .unwrap();
+
         loop {
-            // process notes
-            let mut notes = midi_cc_receiver.recv().unwrap();
-            for note in notes.iter_mut() {
-                if note.started && note.duration == 0 {
-                    note.stop(&mut conn);
-                } else if !note.started {
-                    note.stop(&mut conn);
-                    note.start(&mut conn);
-                    conn.send(&[
-                        note.channel,
-                        note.degree,
-                        scale_exponential(note.velocity as f32),
-                    ])
-                        .unwrap();
-                }
-            }
+            thread::park();
         }
     });
 }
 
+// base-36 values 0-z spread linearly over 0-127
+fn scale_velocity(value: u8) -> u8 {
+    (value.min(35) as f32 * (127.0 / 35.0)) as u8
+}
 
-// scale velocity
-fn scale_exponential(input: f32) -> u8 {
-    let old_min = 0.0;
-    let old_max = 36.0;
-    let new_min = 0.0;
-    let new_max = 127.0;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // scale input to 0-1
-    let normalized = (input - old_min) / (old_max - old_min);
+    #[test]
+    fn notes_reach_only_the_port_they_name() {
+        let notes = [
+            Note { port: 0, note_number: 60, ..Default::default() },
+            Note { port: 2, note_number: 64, ..Default::default() },
+        ];
+        let mut outputs: HashMap<Option<usize>, Vec<u8>> = HashMap::new();
+        for note in &notes {
+            outputs.entry(note_output(note.port, 0, 3)).or_default().push(note.note_number);
+        }
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[&None], [60]);
+        assert_eq!(outputs[&Some(2)], [64]);
 
-    // apply exponential function
-    let exp = 2.0_f32.powf(normalized);
+        // port numbers past the last port wrap, and the selected port always uses its own connection
+        assert_eq!(note_output(4, 0, 3), Some(1));
+        assert_eq!(note_output(1, 1, 3), None);
+        assert_eq!(note_output(4, 1, 3), None);
+    }
 
-    // scale output to 0-127
-    (exp * (new_max - new_min) + new_min) as u8
+    #[test]
+    fn velocity_curves_agree_at_the_ends_and_bend_in_between() {
+        let curves = [VelocityCurve::Linear, VelocityCurve::Exponential, VelocityCurve::Logarithmic];
+        let at = |value| curves.map(|curve| curve.apply(scale_velocity(value)));
+        assert_eq!(at(0), [0, 0, 0]);
+        assert_eq!(at(18), [65, 27, 99]);
+        assert_eq!(at(35), [127, 127, 127]);
+    }
 }
@@ -10,22 +10,16 @@ use crossbeam::channel::Sender;
 use midir::MidiOutputConnection;
 use parking_lot::Mutex;
 
-use crate::{context::{Context, AppState}, NoteSenders, operators::get_tick_operators,
+use crate::{context::{write_session_files, Context, AppState}, NoteSenders, operators::get_tick_operators,
             operators::get_bang_operators,
-            operators::grid_tick,
+            operators::{grid_tick, GlyphTable},
             operators::read_operator_config,
+            midi::CLOCK_PULSES_PER_BEAT,
             utils::{NATURAL_NOTES, SHARP_NOTES}};
 
 const NOTE_ON_MESSAGE: u8 = 0x90;
 const NOTE_OFF_MESSAGE: u8 = 0x80;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct MidiCC {
-    pub channel: u8,
-    pub command: u8,
-    pub value: u8,
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Note {
     pub note_type: u8,
@@ -40,9 +34,56 @@ pub struct Note {
     pub started: bool,
     pub degree: u8,
     pub speed: u8,
+    pub port: u8,
+    // attack, decay, sustain and release in base-36, None keeps the default pluck
+    pub adsr: Option<(u8, u8, u8, u8)>,
+    // plays samples backwards
+    pub reverse: bool,
+    // skips this many 36ths into the sample before playing it
+    pub offset: u8,
+    // a sample in choke group 1-z cuts off the one still playing in the same group, 0 never chokes
+    pub choke: u8,
+    // sample level in 18ths, so `i` leaves it as recorded
+    pub gain: u8,
+    // the sample folder the sampler picks from
+    pub bank: u8,
+    // sent with the MIDI note off, most devices expect 0
+    pub release_velocity: u8,
+    // synth low-pass cutoff and resonance, 0-z each; None leaves the filter open
+    pub filter: Option<(u8, u8)>,
+}
+
+// a silent note: ports left empty, samples at their recorded level and the synth filter open
+impl Default for Note {
+    fn default() -> Note {
+        Note {
+            note_type: 0,
+            channel: 0,
+            engine: 0,
+            sample: 0,
+            slot: 0,
+            note_number: 0,
+            velocity: 0,
+            duration: 0,
+            reverb: 0,
+            started: false,
+            degree: 0,
+            speed: 0,
+            port: 0,
+            adsr: None,
+            reverse: false,
+            offset: 0,
+            choke: 0,
+            gain: 18,
+            bank: 0,
+            release_velocity: 0,
+            filter: None,
+        }
+    }
 }
 
 impl Note {
+    #[allow(clippy::too_many_arguments)]
     pub fn from_base_36(
         note_type: u8,
         channel: u8,
@@ -68,8 +109,9 @@ impl Note {
             false => NATURAL_NOTES[note_index],
         };
 
-        let octave = base_octave + octave_offset;
-        let note_number = 12 * octave + note_offset;
+        // high octaves would overflow u8 and run past the MIDI range, so the sum is clamped to 127
+        let octave = base_octave as u32 + octave_offset as u32;
+        let note_number = (12 * octave + note_offset as u32).min(127) as u8;
         let velocity = (velocity as f32 * (127.0 / 35.0)) as u8;
         let duration = duration as u64 * tick_time;
 
@@ -82,16 +124,16 @@ impl Note {
             note_number,
             velocity,
             duration,
-            started: false,
             degree,
             reverb,
             speed,
+            ..Default::default()
         }
     }
 
+    // operators clamp channels, the clamp here only keeps release builds from sending a wrong status byte
     pub fn start(&mut self, conn: &mut MidiOutputConnection) {
-        let note_on_message: u8 = NOTE_ON_MESSAGE + self.channel;
-        if let Err(err) = conn.send(&[note_on_message, self.note_number, self.velocity]) {
+        if let Err(err) = conn.send(&self.note_on_message()) {
             println!("Midi note on send error: {}", err);
         } else {
             self.started = true;
@@ -99,17 +141,32 @@ impl Note {
     }
 
     pub fn stop(&self, conn: &mut MidiOutputConnection) {
-        let note_off_message: u8 = NOTE_OFF_MESSAGE + self.channel;
-        if let Err(err) = conn.send(&[note_off_message, self.note_number, self.velocity]) {
+        if let Err(err) = conn.send(&self.note_off_message()) {
             println!("Midi note off send error: {}", err);
         }
     }
+
+    // a channel past 15 would spill into the next message type, so it is capped
+    pub fn note_on_message(&self) -> [u8; 3] {
+        debug_assert!(self.channel <= 15, "midi channel {} out of range", self.channel);
+        [NOTE_ON_MESSAGE + self.channel.min(15), self.note_number, self.velocity]
+    }
+
+    pub fn note_off_message(&self) -> [u8; 3] {
+        debug_assert!(self.channel <= 15, "midi channel {} out of range", self.channel);
+        [NOTE_OFF_MESSAGE + self.channel.min(15), self.note_number, self.release_velocity]
+    }
+}
+
+// notes that would sound on the same voice share a key, so a fresh note replaces the one still playing
+fn voice_key(note: &Note) -> (u8, u8, u8, u8, u8, u8) {
+    (note.note_type, note.port, note.channel, note.note_number, note.sample, note.degree)
 }
 
 pub fn notes_tick(notes: &[Note], tick_time: u64) -> Vec<Note> {
-    let mut note_set: HashMap<(u8, u8), Note> = HashMap::new();
+    let mut note_set: HashMap<(u8, u8, u8, u8, u8, u8), Note> = HashMap::new();
     for note in notes {
-        let key = (note.channel, note.note_number);
+        let key = voice_key(note);
         if note.started {
             let duration = note.duration.saturating_sub(tick_time);
             if let Some(other_note) = note_set.get(&key) {
@@ -138,48 +195,136 @@ fn process_and_send_notes(
         midi_notes,
         tick_time as u64
     );
+    // each output gets one batch per tick: new notes to start, plus MIDI notes that just ran out
     let mut midi_notes_to_play = Vec::new();
-    let mut midi_cc_to_play = Vec::new();
     let mut sampler_notes_to_play = Vec::new();
     let mut synth_notes_to_play = Vec::new();
+    let mut midi_cc_to_play = Vec::new();
+    let mut pitch_bends_to_play = Vec::new();
     for note in processed_notes.iter_mut() {
         match note.note_type {
-            0 => {
+            0 => if !note.started || note.duration == 0 {
                 midi_notes_to_play.push(*note);
-                let _ = note_senders.midi_note_sender.send(midi_notes_to_play.clone());
-                note.started = true;
-                midi_port_sender.send(midi_port).unwrap();
-            }
+            },
             1 => if !note.started {
                 synth_notes_to_play.push(*note);
-                let _ = note_senders.synth_note_sender.send(synth_notes_to_play.clone());
-                note.started = true;
             },
             2 => if !note.started {
                 sampler_notes_to_play.push(*note);
-                let _ = note_senders.sampler_note_sender.send(sampler_notes_to_play.clone());
-                note.started = true;
             },
-            3 => {
+            3 => if !note.started {
                 midi_cc_to_play.push(*note);
-                let _ = note_senders.midi_cc_sender.send(midi_cc_to_play.clone());
-                note.started = true;
-            }
+            },
+            4 => if !note.started {
+                pitch_bends_to_play.push(*note);
+            },
             _ => println!("bam"),
         }
+        note.started = true;
+    }
+    if !midi_notes_to_play.is_empty() {
+        midi_port_sender.send(midi_port).unwrap();
+        let _ = note_senders.midi_note_sender.send(midi_notes_to_play);
+    }
+    if !synth_notes_to_play.is_empty() {
+        let _ = note_senders.synth_note_sender.send(synth_notes_to_play);
+    }
+    if !sampler_notes_to_play.is_empty() {
+        let _ = note_senders.sampler_note_sender.send(sampler_notes_to_play);
+    }
+    if !midi_cc_to_play.is_empty() {
+        let _ = note_senders.midi_cc_sender.send(midi_cc_to_play);
+    }
+    if !pitch_bends_to_play.is_empty() {
+        let _ = note_senders.pitch_bend_sender.send(pitch_bends_to_play);
     }
     processed_notes.iter().filter(|note| note.duration > 0).cloned().collect()
 }
 
+// sends a stopped copy of every note, so each output releases whatever it is still playing
+fn flush_notes(notes: &[Note], note_senders: &NoteSenders) {
+    let stopped_notes: Vec<Note> = notes
+        .iter()
+        .map(|note| Note {
+            duration: 0,
+            started: true,
+            ..*note
+        })
+        .collect();
+    let of_type = |note_type: u8| -> Vec<Note> {
+        stopped_notes
+            .iter()
+            .filter(|note| note.note_type == note_type)
+            .cloned()
+            .collect()
+    };
+
+    let _ = note_senders.midi_note_sender.send(of_type(0));
+    let _ = note_senders.synth_note_sender.send(of_type(1));
+    let _ = note_senders.sampler_note_sender.send(of_type(2));
+}
+
+// the metronome counts divisions per beat, the first of each beat is the downbeat
+pub fn is_downbeat(tick: usize, divisions: u64) -> bool {
+    tick.is_multiple_of(divisions.max(1) as usize)
+}
+
+// a short high blip on the kick voice of the synth, louder on downbeats
+fn metronome_click(downbeat: bool) -> Note {
+    Note {
+        note_type: 1,
+        engine: 4,
+        note_number: if downbeat { 96 } else { 84 },
+        velocity: if downbeat { 35 } else { 15 },
+        duration: 50,
+        ..Default::default()
+    }
+}
+
+// the 24 clock pulses of a beat split over its ticks, so every tick boundary falls on a pulse and divisions
+// that don't divide 24 still send exactly 24 per beat
+pub fn clock_pulses_for_tick(tick: usize, divisions: u64) -> u64 {
+    let divisions = divisions.max(1);
+    let tick = tick as u64 % divisions;
+    CLOCK_PULSES_PER_BEAT * (tick + 1) / divisions - CLOCK_PULSES_PER_BEAT * tick / divisions
+}
+
+// when following external clock a tick is due once the pulses clock out would send for it arrived,
+// so incoming and outgoing clock split a beat the same way
+fn take_tick_pulses(context: &mut Context) -> bool {
+    if !context.clock_follow {
+        return true;
+    }
+    let pulses = clock_pulses_for_tick(context.ticks, context.divisions);
+    if context.clock_pulses < pulses {
+        return false;
+    }
+    context.clock_pulses -= pulses;
+    true
+}
+
+// swing stretches the first tick of each pair and shortens the second by the same amount, so a pair
+// always lasts two straight ticks; full swing is a 2:1 triplet shuffle
+pub fn swung_tick_duration(tick_duration: Duration, swing: u8, tick: usize) -> Duration {
+    let shift = tick_duration.mul_f64(swing.min(100) as f64 / 300.0);
+    if tick.is_multiple_of(2) {
+        tick_duration + shift
+    } else {
+        tick_duration - shift
+    }
+}
+
 pub fn run_notes(
     notes_context_arc: Arc<Mutex<Context>>,
     should_redraw_notes: Arc<AtomicBool>,
     note_senders: NoteSenders,
     midi_port_sender: Sender<usize>,
 ) {
-    let operator_map = read_operator_config("operator_config.txt");
+    let operator_map = read_operator_config(&notes_context_arc.lock().operator_config);
     let tick_operators = get_tick_operators(&operator_map);
     let bang_operators = get_bang_operators(&operator_map);
+    let tick_glyphs = GlyphTable::new(&tick_operators);
+    let bang_glyphs = GlyphTable::new(&bang_operators);
     spawn(move || {
         let mut next_tick = Instant::now();
         loop {
@@ -188,14 +333,36 @@ pub fn run_notes(
                 // Get and lock app state
                 let mut context_locked = notes_context_arc.lock();
 
-                if context_locked.app_state == AppState::Running {
+                let following = context_locked.clock_follow;
+
+                if context_locked.app_state == AppState::Running && take_tick_pulses(&mut context_locked) {
+                    if context_locked.metronome {
+                        let downbeat = is_downbeat(context_locked.ticks, context_locked.divisions);
+                        context_locked.write_note(metronome_click(downbeat));
+                    }
                     grid_tick(
                         &mut context_locked,
                         &tick_operators,
                         &bang_operators,
+                        &tick_glyphs,
+                        &bang_glyphs,
                         should_redraw_notes.clone(),
                     );
 
+                    // only ticks while running reach here, so a paused session is never autosaved
+                    let autosave_ticks = context_locked.autosave_ticks;
+                    // the files are written on their own thread, so a slow or full disk never holds up playback
+                    if autosave_ticks > 0 && context_locked.ticks.is_multiple_of(autosave_ticks) {
+                        let files = context_locked.session_files("autosave");
+                        let autosave_context_arc = Arc::clone(&notes_context_arc);
+                        spawn(move || {
+                            if let Err(err) = write_session_files(&files) {
+                                let message = format!("autosave failed: {}", err);
+                                autosave_context_arc.lock().status_message = Some((message, Instant::now()));
+                            }
+                        });
+                    }
+
 
                     let midi_notes = context_locked.notes.clone();
                     let tick_time = context_locked.tick_time;
@@ -208,8 +375,33 @@ pub fn run_notes(
                         &midi_port_sender
                     );
 
-                    let tick_duration = Duration::from_secs_f64(60.0 / (context_locked.divisions * context_locked.tempo) as f64);
-                    next_tick += tick_duration;
+                    let tick_duration = swung_tick_duration(
+                        Duration::from_secs_f64(60.0 / (context_locked.divisions * context_locked.tempo) as f64),
+                        context_locked.swing,
+                        context_locked.ticks - 1,
+                    );
+
+                    // clock out follows the ticks, so pulses land on tick boundaries and swing with them
+                    if context_locked.midi_clock {
+                        let pulses = clock_pulses_for_tick(context_locked.ticks - 1, context_locked.divisions);
+                        let _ = note_senders.clock_pulse_sender.send((pulses, tick_duration));
+                    }
+                    next_tick = if following { now } else { next_tick + tick_duration };
+                } else if context_locked.app_state == AppState::Running {
+                    // wait for the next clock pulse
+                    next_tick = now + Duration::from_millis(1);
+                } else {
+                    // silence everything that is still sounding once playback is paused
+                    if !context_locked.notes.is_empty() {
+                        flush_notes(&context_locked.notes, &note_senders);
+                        context_locked.notes.clear();
+                    }
+
+                    // external clock pulses don't pile up while paused, see run_midi_in
+                    context_locked.clock_pulses = 0;
+
+                    // idle until playback resumes, then continue from now instead of catching up
+                    next_tick = now + Duration::from_millis(1);
                 }
                 drop(context_locked);
             } else {
@@ -219,3 +411,145 @@ pub fn run_notes(
     });
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crossbeam::channel::Receiver;
+
+    use crate::midi::{control_change_message, VelocityCurve};
+
+    use super::*;
+
+    #[test]
+    fn a_bar_sends_96_clock_pulses() {
+        for divisions in 1..=12 {
+            let bar: u64 = (0..4 * divisions as usize).map(|tick| clock_pulses_for_tick(tick, divisions)).sum();
+            assert_eq!(bar, 4 * CLOCK_PULSES_PER_BEAT, "divisions {}", divisions);
+        }
+        // four divisions split a beat into even sixes
+        assert!((0..4).all(|tick| clock_pulses_for_tick(tick, 4) == 6));
+    }
+
+    // senders for every output, with the receivers of the midi, synth, sampler and CC notes
+    fn note_senders() -> (NoteSenders, [Receiver<Vec<Note>>; 4]) {
+        let (midi_note_sender, midi_notes) = crossbeam::channel::unbounded();
+        let (synth_note_sender, synth_notes) = crossbeam::channel::unbounded();
+        let (sampler_note_sender, sampler_notes) = crossbeam::channel::unbounded();
+        let (midi_cc_sender, midi_cc) = crossbeam::channel::unbounded();
+        let (pitch_bend_sender, _) = crossbeam::channel::unbounded();
+        let (clock_pulse_sender, _) = crossbeam::channel::unbounded();
+        let note_senders = NoteSenders {
+            midi_note_sender,
+            sampler_note_sender,
+            midi_cc_sender,
+            synth_note_sender,
+            pitch_bend_sender,
+            clock_pulse_sender,
+        };
+        (note_senders, [midi_notes, synth_notes, sampler_notes, midi_cc])
+    }
+
+    #[test]
+    fn pausing_stops_every_sounding_note() {
+        let (note_senders, [midi_notes, synth_notes, sampler_notes, _]) = note_senders();
+        let sounding = [0, 1, 2, 0].map(|note_type| Note {
+            note_type,
+            note_number: 60,
+            duration: 500,
+            started: true,
+            ..Default::default()
+        });
+
+        flush_notes(&sounding, &note_senders);
+        let stopped: Vec<Note> = [midi_notes, synth_notes, sampler_notes]
+            .iter()
+            .flat_map(|notes| notes.try_recv().unwrap())
+            .collect();
+        assert_eq!(stopped.len(), sounding.len());
+        assert!(stopped.iter().all(|note| note.started && note.duration == 0));
+    }
+
+    #[test]
+    fn a_channel_15_note_off_has_status_0x8f() {
+        let note = Note { channel: 15, note_number: 60, velocity: 100, release_velocity: 64, ..Default::default() };
+        assert_eq!(note.note_off_message(), [0x8F, 60, 64]);
+        assert_eq!(note.note_on_message(), [0x9F, 60, 100]);
+        // the release velocity defaults to 0
+        assert_eq!(Note { channel: 15, note_number: 60, ..Default::default() }.note_off_message(), [0x8F, 60, 0]);
+    }
+
+    #[test]
+    fn retriggered_notes_dont_pile_up_over_many_ticks() {
+        let (note_senders, _receivers) = note_senders();
+        let (midi_port_sender, _midi_port) = crossbeam::channel::unbounded();
+        let mut notes: Vec<Note> = Vec::new();
+        for tick in 0..1000 {
+            // every tick retriggers the same four notes, each held for eight ticks, plus one passing note
+            notes.extend((0..4).map(|note_type| Note {
+                note_type,
+                note_number: 60,
+                duration: 8 * 125,
+                ..Default::default()
+            }));
+            notes.push(Note { note_type: 1, note_number: (tick % 128) as u8, duration: 125, ..Default::default() });
+            notes = process_and_send_notes(&notes, 125.0, 0, &note_senders, &midi_port_sender);
+            assert!(notes.len() <= 5, "{} notes after tick {}", notes.len(), tick);
+        }
+    }
+
+    #[test]
+    fn swung_pairs_last_two_straight_ticks() {
+        let tick = Duration::from_millis(120);
+        assert_eq!(swung_tick_duration(tick, 0, 0), tick);
+        assert_eq!(swung_tick_duration(tick, 0, 1), tick);
+
+        assert_eq!(swung_tick_duration(tick, 50, 0), Duration::from_millis(140));
+        assert_eq!(swung_tick_duration(tick, 50, 1), Duration::from_millis(100));
+        for swing in [0, 25, 50, 100] {
+            for first in [0, 2, 6] {
+                let pair = swung_tick_duration(tick, swing, first) + swung_tick_duration(tick, swing, first + 1);
+                assert_eq!(pair, 2 * tick, "swing {}", swing);
+            }
+        }
+    }
+
+    #[test]
+    fn a_cc_bang_sends_one_control_change_and_no_note() {
+        let (note_senders, [midi_notes, synth_notes, sampler_notes, midi_cc]) = note_senders();
+        let (midi_port_sender, _midi_port) = crossbeam::channel::unbounded();
+        // what the CC operator emits for channel 2, controller 7 and value z
+        let cc = Note { note_type: 3, channel: 0xB2, degree: 7, velocity: 35, duration: 1, ..Default::default() };
+        process_and_send_notes(&[cc], 125.0, 0, &note_senders, &midi_port_sender);
+
+        let sent = midi_cc.try_recv().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(control_change_message(&sent[0], VelocityCurve::Linear), [0xB2, 7, 127]);
+        assert!(midi_notes.try_recv().is_err() && synth_notes.try_recv().is_err() && sampler_notes.try_recv().is_err());
+    }
+
+    #[test]
+    fn the_first_tick_of_each_beat_is_the_downbeat() {
+        let downbeats: Vec<bool> = (0..8).map(|tick| is_downbeat(tick, 4)).collect();
+        assert_eq!(downbeats, [true, false, false, false, true, false, false, false]);
+        assert!((0..5).all(|tick| is_downbeat(tick, 1)));
+        // divisions of 0 count as 1 rather than dividing by zero
+        assert!(is_downbeat(3, 0));
+        assert!(is_downbeat(6, 3) && !is_downbeat(7, 3));
+    }
+
+    #[test]
+    fn following_a_beat_of_clock_ticks_once_per_division() {
+        for divisions in [4, 5, 7, 24] {
+            let mut context = Context::new(120, divisions, 8, 8, "new", Some(0), "operator_config.txt");
+            context.clock_follow = true;
+            context.clock_pulses = CLOCK_PULSES_PER_BEAT;
+            let mut ticks = 0;
+            while take_tick_pulses(&mut context) {
+                context.ticks += 1;
+                ticks += 1;
+            }
+            assert_eq!(ticks, divisions, "divisions {}", divisions);
+            assert_eq!(context.clock_pulses, 0);
+        }
+    }
+}
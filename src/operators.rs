@@ -2,7 +2,6 @@ use copypasta::{ClipboardContext, ClipboardProvider};
 use rand::{
     distributions::Bernoulli,
     prelude::Distribution,
-    thread_rng,
     Rng
 };
 use std::{
@@ -11,12 +10,17 @@ use std::{
     io::{Read, Write},
     path::Path,
     sync::atomic::{AtomicBool, Ordering},
-    sync::Arc
+    sync::Arc,
+    time::Instant
 };
 use crate::context::{Context, Globals, Port};
+use crate::midi::{CONTROL_CHANGE_MESSAGE, PITCH_BEND_CENTER};
 use crate::note_events::Note;
+use crate::utils::{note_to_key, CHORDS, NATURAL_NOTES, SCALES, SHARP_NOTES};
 
-use crate::utils::{NATURAL_NOTES, SCALES, SHARP_NOTES};
+const CHANCE_GATE: char = '$';
+// right of Sub's b input, switches it from wrapping to the absolute difference
+const SUB_ABSOLUTE: char = ',';
 
 pub fn char_to_base_36(c: char) -> (u8, bool) {
     match c {
@@ -71,7 +75,14 @@ impl Operator {
         }
     }
 
-    fn apply(&self, context: &mut Context, row: i32, col: i32) {
+    // name of a port, falling back to the operator's own name when an operator reports more ports than it
+    // names so a mismatch can't take down the tick thread
+    fn port_name(names: &[String], index: usize, operator: &str) -> String {
+        names.get(index).cloned().unwrap_or_else(|| operator.to_string())
+    }
+
+    // an operator that doesn't fire still claims its ports, but has no effects
+    fn apply(&self, context: &mut Context, row: i32, col: i32, fire: bool) {
         if !context.is_locked(row, col) {
             let updates = (self.evaluate)(context, row, col);
             for update in updates {
@@ -81,17 +92,19 @@ impl Operator {
                             context.lock_with_name(
                                 port.row,
                                 port.col,
-                                self.input_ports[index].clone(),
+                                Operator::port_name(&self.input_ports, index, &self.name),
                             );
                         }
                     }
                     Update::Outputs(ports) => {
                         for (index, port) in ports.iter().enumerate() {
-                            context.write(port.row, port.col, port.value);
+                            if fire {
+                                context.write(port.row, port.col, port.value);
+                            }
                             context.lock_with_name(
                                 port.row,
                                 port.col,
-                                self.output_ports[index].clone(),
+                                Operator::port_name(&self.output_ports, index, &self.name),
                             );
                         }
                     }
@@ -100,26 +113,29 @@ impl Operator {
                             context.lock(port.row, port.col);
                         }
                     }
-                    Update::Notes(notes) => {
+                    Update::Notes(notes) if fire => {
                         for note in notes {
                             context.write_note(note);
                         }
                     }
-                    Update::Globals(globals) => {
+                    Update::Globals(globals) if fire => {
                         context.global_key = globals.global_key;
                         context.global_scale = globals.global_scale;
                     }
-                    Update::Load(name) => {
+                    Update::Load(name) if fire => {
                         context.load(name);
                     }
-                    Update::Save(name) => {
-                        context.save(name);
+                    Update::Save(name) if fire => {
+                        if let Err(err) = context.save(name) {
+                            context.status_message = Some((format!("save failed: {}", err), Instant::now()));
+                        }
                     }
-                    Update::Variables(variables) => {
+                    Update::Variables(variables) if fire => {
                         for (name, value) in variables {
                             context.set_variable(name, value);
                         }
                     }
+                    _ => {}
                 }
             }
         }
@@ -128,6 +144,7 @@ impl Operator {
 
 pub fn read_operator_config(filename: &str) -> HashMap<String, char> {
     let default_operator_config = "
+A Add
 B Sub
 C Clock
 D Delay
@@ -166,6 +183,15 @@ Z Interpolate
 ] Loader
 { SnipSave
 } SnipLoad
+% Modulo
+/ Divide
+& MidiIn
+) Greater
+| Quantize
+! PitchBend
+< Arp
+( Chord
+' NoteName
 "
         .trim()
         .to_string();
@@ -259,9 +285,19 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
         Operator::new(
             "Sub",
             sub,
-            vec!["Input A".to_string(), "Input B".to_string()],
+            vec![
+                "Input A".to_string(),
+                "Input B".to_string(),
+                "Mode".to_string(),
+            ],
             vec!["A-B".to_string()],
         ),
+        Operator::new(
+            "Modulo",
+            modulo,
+            vec!["Input A".to_string(), "Input B".to_string()],
+            vec!["A%B".to_string()],
+        ),
         Operator::new(
             "Clock",
             clock,
@@ -271,7 +307,7 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
         Operator::new(
             "Delay",
             delay,
-            vec!["Input A".to_string(), "Input B".to_string()],
+            vec!["Input A".to_string(), "Input B".to_string(), "Offset".to_string()],
             vec!["Output".to_string()],
         ),
         Operator::new(
@@ -336,7 +372,7 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
             "Halt",
             halt,
             vec!["Input A".to_string()],
-            vec!["Output".to_string()],
+            vec!["Halted".to_string()],
         ),
         Operator::new(
             "Increment",
@@ -381,12 +417,24 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
             vec!["Input A".to_string(), "Input B".to_string()],
             vec!["<".to_string()],
         ),
+        Operator::new(
+            "Greater",
+            greater,
+            vec!["Input A".to_string(), "Input B".to_string()],
+            vec![">".to_string()],
+        ),
         Operator::new(
             "Multiply",
             multiply,
             vec!["Input A".to_string(), "Input B".to_string()],
             vec!["A*B".to_string()],
         ),
+        Operator::new(
+            "Divide",
+            divide,
+            vec!["Input A".to_string(), "Input B".to_string()],
+            vec!["A/B".to_string()],
+        ),
         Operator::new(
             "North",
             north,
@@ -520,46 +568,11 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
         Operator::new(
             "Track",
             track,
-            vec![
-                "Step".to_string(),
-                "Steps".to_string(),
-                "Input 0".to_string(),
-                "Input 1".to_string(),
-                "Input 2".to_string(),
-                "Input 3".to_string(),
-                "Input 4".to_string(),
-                "Input 5".to_string(),
-                "Input 6".to_string(),
-                "Input 7".to_string(),
-                "Input 8".to_string(),
-                "Input 9".to_string(),
-                "Input A".to_string(),
-                "Input B".to_string(),
-                "Input C".to_string(),
-                "Input D".to_string(),
-                "Input E".to_string(),
-                "Input F".to_string(),
-                "Input G".to_string(),
-                "Input H".to_string(),
-                "Input I".to_string(),
-                "Input G".to_string(),
-                "Input K".to_string(),
-                "Input L".to_string(),
-                "Input M".to_string(),
-                "Input N".to_string(),
-                "Input O".to_string(),
-                "Input P".to_string(),
-                "Input Q".to_string(),
-                "Input R".to_string(),
-                "Input S".to_string(),
-                "Input T".to_string(),
-                "Input U".to_string(),
-                "Input V".to_string(),
-                "Input W".to_string(),
-                "Input X".to_string(),
-                "Input Y".to_string(),
-                "Input Z".to_string(),
-            ],
+            // one label per step, named after the glyph the step is selected with
+            ["Step".to_string(), "Steps".to_string()]
+                .into_iter()
+                .chain((0..36).map(|i| format!("Input {}", base_36_to_char(i, true))))
+                .collect(),
             vec!["Output Step".to_string()],
         ),
         Operator::new(
@@ -623,6 +636,12 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
                 "Duration".to_string(),
                 "Reverb".to_string(),
                 "FM".to_string(),
+                "Attack".to_string(),
+                "Decay".to_string(),
+                "Sustain".to_string(),
+                "Release".to_string(),
+                "Cutoff".to_string(),
+                "Resonance".to_string(),
             ],
             vec!["Output".to_string()],
         ),
@@ -636,6 +655,11 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
                 "Duration".to_string(),
                 "Reverb".to_string(),
                 "Speed".to_string(),
+                "Reverse".to_string(),
+                "Bank".to_string(),
+                "Offset".to_string(),
+                "Choke".to_string(),
+                "Gain".to_string(),
             ],
             vec!["Output".to_string()],
         ),
@@ -649,9 +673,17 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
                 "Base Note".to_string(),
                 "Velocity".to_string(),
                 "Duration".to_string(),
+                "Port".to_string(),
+                "Release".to_string(),
             ],
             vec!["Output".to_string()],
         ),
+        Operator::new(
+            "MidiIn",
+            midi_in,
+            vec!["Channel".to_string()],
+            vec!["Octave".to_string(), "Note".to_string(), "Velocity".to_string()],
+        ),
         Operator::new(
             "MidiCC",
             midi_cc,
@@ -662,6 +694,16 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
             ],
             vec!["Output".to_string()],
         ),
+        Operator::new(
+            "PitchBend",
+            pitch_bend,
+            vec![
+                "Channel".to_string(),
+                "Bend".to_string(),
+                "Fine".to_string(),
+            ],
+            vec!["Output".to_string()],
+        ),
         Operator::new(
             "Scaler",
             scaler,
@@ -671,13 +713,53 @@ pub fn get_tick_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
                 "Degree".to_string(),
                 "Velocity".to_string(),
                 "Duration".to_string(),
+                "Port".to_string(),
             ],
             vec!["Output".to_string()],
         ),
+        Operator::new(
+            "Chord",
+            chord,
+            vec![
+                "Channel".to_string(),
+                "Octave".to_string(),
+                "Note".to_string(),
+                "Chord".to_string(),
+                "Velocity".to_string(),
+                "Duration".to_string(),
+            ],
+            vec!["Output".to_string()],
+        ),
+        Operator::new(
+            "Arp",
+            arp,
+            vec![
+                "Channel".to_string(),
+                "Octave".to_string(),
+                "Degree".to_string(),
+                "Count".to_string(),
+                "Rate".to_string(),
+                "Velocity".to_string(),
+                "Duration".to_string(),
+            ],
+            vec!["Output".to_string()],
+        ),
+        Operator::new(
+            "Quantize",
+            quantize,
+            vec!["Semitones".to_string()],
+            vec!["Degree".to_string()],
+        ),
+        Operator::new(
+            "NoteName",
+            note_name,
+            vec!["Note high".to_string(), "Note low".to_string()],
+            vec!["Octave".to_string(), "Note".to_string()],
+        ),
         Operator::new(
             "Bernoulli",
             bernoulli,
-            vec!["Probability".to_string()],
+            vec!["Probability 0-z".to_string()],
             vec!["Output A".to_string(), "Output B".to_string()],
         ),
     ]
@@ -728,11 +810,40 @@ fn add(context: &Context, row: i32, col: i32) -> Vec<Update> {
 fn sub(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let a_port = context.listen("a", row, col - 1, '0');
     let b_port = context.listen("b", row, col + 1, '0');
+    // the mode port right of b only counts when it holds SUB_ABSOLUTE, anything else there is left alone
+    let absolute = context.read(row, col + 2) == SUB_ABSOLUTE;
 
     let (a, a_upper) = char_to_base_36(a_port.value);
     let (b, b_upper) = char_to_base_36(b_port.value);
-    let diff = if a > b { a - b } else { b - a };
-    let out = base_36_to_char(diff, a_upper || b_upper);
+    let out = if absolute {
+        base_36_to_char(a.abs_diff(b), a_upper || b_upper)
+    } else {
+        // a - b wraps around 36, and an upper case result signals that b was larger than a; digits
+        // have no case, so an underflow that lands on 0-9 can't be told apart
+        let diff = (a + 36 - b) % 36;
+        base_36_to_char(diff, a_upper || b_upper || a < b)
+    };
+
+    let out_port = Port::new("out", row + 1, col, out);
+
+    let mut input_ports = vec![a_port, b_port];
+    if absolute {
+        input_ports.push(Port::new("mode", row, col + 2, SUB_ABSOLUTE));
+    }
+
+    vec![
+        Update::Inputs(input_ports),
+        Update::Outputs(vec![out_port]),
+    ]
+}
+
+fn modulo(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let a_port = context.listen("a", row, col - 1, '0');
+    let b_port = context.listen("b", row, col + 1, '0');
+
+    let (a, a_upper) = char_to_base_36(a_port.value);
+    let (b, b_upper) = char_to_base_36(b_port.value);
+    let out = base_36_to_char(a % b.max(1), a_upper || b_upper);
 
     let out_port = Port::new("out", row + 1, col, out);
 
@@ -745,19 +856,25 @@ fn sub(context: &Context, row: i32, col: i32) -> Vec<Update> {
 fn delay(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let rate_port = context.listen("rate", row, col - 1, '1');
     let mod_port = context.listen("mod", row, col + 1, '8');
+    // the offset sits two cells right, where patches made before it may already keep something; that cell is
+    // now read and locked like the other inputs, see the Delay section of the README
+    let offset_port = context.listen("offset", row, col + 2, '0');
 
     let (rate, _) = char_to_base_36(rate_port.value);
     let (delay_mod, _) = char_to_base_36(mod_port.value);
+    let (offset, _) = char_to_base_36(offset_port.value);
     let rate = rate.max(1);
     let delay_mod = delay_mod.max(1);
 
+    // the offset pushes the bang that many ticks later within the period
+    let period = rate as usize * delay_mod as usize;
     let mut out_port = context.listen("out", row + 1, col, '.');
-    if context.ticks % (rate as usize * delay_mod as usize) == 0 {
+    if context.ticks % period == offset as usize % period {
         out_port.value = '*';
     }
 
     vec![
-        Update::Inputs(vec![rate_port, mod_port]),
+        Update::Inputs(vec![rate_port, mod_port, offset_port]),
         Update::Outputs(vec![out_port]),
     ]
 }
@@ -768,11 +885,10 @@ fn random(context: &Context, row: i32, col: i32) -> Vec<Update> {
 
     let (min, min_upper) = char_to_base_36(min_port.value);
     let (max, max_upper) = char_to_base_36(max_port.value);
-    let max = max.max(min + 1); // wow this looks like trash
-
+    // swapped inputs are reordered, and equal inputs always produce that value
+    let (min, max) = (min.min(max), min.max(max));
 
-    let mut rng = thread_rng();
-    let r = rng.gen_range(min..max);
+    let r = if min == max { min } else { context.rng.borrow_mut().gen_range(min..max) };
     let out = base_36_to_char(r, min_upper || max_upper);
     let out_port = Port::new("out", row + 1, col, out);
 
@@ -788,7 +904,8 @@ fn scaler(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let degree_port = context.listen("degree", row, col + 3, '0');
     let velocity_port = context.listen("velocity", row, col + 4, 'u');
     let duration_port = context.listen("duration", row, col + 5, '2');
-    let (channel, _) = char_to_base_36(channel_port.value);
+    let port_port = context.listen("port", row, col + 6, '\0');
+    let channel = midi_channel(channel_port.value);
     let (octave, _) = char_to_base_36(octave_port.value);
     let (note, note_upper) = char_to_base_36(context.global_key);
     let (velocity, _) = char_to_base_36(velocity_port.value);
@@ -800,8 +917,8 @@ fn scaler(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let note_number = prepare_note(octave, note_upper, degree, scale, octave_offset, note_index as usize);
     let velocity = (velocity as f32 * (127.0 / 35.0)) as u8;
     let duration = duration as u64 * context.tick_time;
+    let port = midi_output_port(context, port_port.value);
 
-    let (engine, sample, reverb, speed, slot) = (0, 0, 0, 0, 0);
     let midi_notes = if context.read(row - 1, col) == '*'
         || context.read(row, col - 1) == '*'
         || context.read(row + 1, col) == '*'
@@ -809,16 +926,12 @@ fn scaler(context: &Context, row: i32, col: i32) -> Vec<Update> {
         vec![Note {
             note_type: 0,
             channel,
-            engine,
-            sample,
-            slot,
             note_number,
             velocity,
             duration,
-            started: false,
             degree,
-            reverb,
-            speed,
+            port,
+            ..Default::default()
         }]
     } else {
         vec![]
@@ -831,46 +944,200 @@ fn scaler(context: &Context, row: i32, col: i32) -> Vec<Update> {
             degree_port,
             velocity_port,
             duration_port,
+            port_port,
         ]),
         Update::Notes(midi_notes),
     ]
 }
 
+// a note glyph stacks the chord type's intervals on it; a digit is a degree of the global scale that
+// gets thirds of the scale stacked on it, as many as the chord type has members
+fn chord(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let channel_port = context.listen("channel", row, col + 1, '0');
+    let octave_port = context.listen("octave", row, col + 2, '2');
+    let note_port = context.listen("note", row, col + 3, 'C');
+    let chord_port = context.listen("chord", row, col + 4, '0');
+    let velocity_port = context.listen("velocity", row, col + 5, 'u');
+    let duration_port = context.listen("duration", row, col + 6, '1');
+
+    let channel = midi_channel(channel_port.value);
+    let (octave, _) = char_to_base_36(octave_port.value);
+    let (note, note_upper) = char_to_base_36(note_port.value);
+    let (chord, _) = char_to_base_36(chord_port.value);
+    let (velocity, _) = char_to_base_36(velocity_port.value);
+    let (duration, _) = char_to_base_36(duration_port.value);
+    let (key, key_upper) = char_to_base_36(context.global_key);
+    let (scale, _) = char_to_base_36(context.global_scale);
+    let intervals = CHORDS[chord as usize % CHORDS.len()];
+    let scale_degree = note < 10;
+
+    let midi_notes = if context.read(row - 1, col) == '*'
+        || context.read(row, col - 1) == '*'
+        || context.read(row + 1, col) == '*'
+    {
+        let (base_note, sharp) = if scale_degree { (key, !key_upper) } else { (note, !note_upper) };
+        let root = Note {
+            port: context.midi_port,
+            ..Note::from_base_36(
+                0,
+                channel,
+                0,
+                0,
+                0,
+                octave,
+                base_note,
+                sharp,
+                0,
+                velocity,
+                duration,
+                0,
+                context.tick_time,
+                0,
+            )
+        };
+        if scale_degree {
+            let note_index = (key - 10) % 7;
+            let octave_offset = 1 + (key - 10) / 7;
+            (0..intervals.len() as u8)
+                .map(|member| {
+                    let degree = note + 2 * member;
+                    Note {
+                        note_number: prepare_note(octave, key_upper, degree, scale, octave_offset, note_index as usize),
+                        degree,
+                        ..root
+                    }
+                })
+                .collect()
+        } else {
+            intervals
+                .iter()
+                .map(|interval| Note {
+                    note_number: root.note_number.saturating_add(*interval).min(127),
+                    ..root
+                })
+                .collect()
+        }
+    } else {
+        vec![]
+    };
+
+    vec![
+        Update::Inputs(vec![
+            channel_port,
+            octave_port,
+            note_port,
+            chord_port,
+            velocity_port,
+            duration_port,
+        ]),
+        Update::Notes(midi_notes),
+    ]
+}
+
+// walks count scale degrees up from the base degree, one note every rate ticks.
+// an empty channel plays the synth, anything else sends MIDI on that channel
+fn arp(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let channel_port = context.listen("channel", row, col + 1, '\0');
+    let octave_port = context.listen("octave", row, col + 2, '2');
+    let degree_port = context.listen("degree", row, col + 3, '0');
+    let count_port = context.listen("count", row, col + 4, '3');
+    let rate_port = context.listen("rate", row, col + 5, '1');
+    let velocity_port = context.listen("velocity", row, col + 6, 'u');
+    let duration_port = context.listen("duration", row, col + 7, '1');
+
+    let channel = midi_channel(channel_port.value);
+    let (octave, _) = char_to_base_36(octave_port.value);
+    let (degree, _) = char_to_base_36(degree_port.value);
+    let (count, _) = char_to_base_36(count_port.value);
+    let (rate, _) = char_to_base_36(rate_port.value);
+    let (velocity, _) = char_to_base_36(velocity_port.value);
+    let (duration, _) = char_to_base_36(duration_port.value);
+    let (note, note_upper) = char_to_base_36(context.global_key);
+    let (scale, _) = char_to_base_36(context.global_scale);
+    let count = count.max(1) as usize;
+    let rate = rate.max(1) as usize;
+
+    let step = (context.ticks / rate % count) as u8;
+    let degree = (degree + step) % 36;
+    let note_index = (note - 10) % 7;
+    let octave_offset = 1 + (note - 10) / 7;
+    let note_number = prepare_note(octave, note_upper, degree, scale, octave_offset, note_index as usize);
+    let velocity = (velocity as f32 * (127.0 / 35.0)) as u8;
+    let duration = duration as u64 * context.tick_time;
+
+    let (note_type, speed) = if channel_port.value == '\0' { (1, 1) } else { (0, 0) };
+    let notes = if context.ticks.is_multiple_of(rate) {
+        vec![Note {
+            note_type,
+            channel,
+            note_number,
+            velocity,
+            duration,
+            degree,
+            speed,
+            port: context.midi_port,
+            ..Default::default()
+        }]
+    } else {
+        vec![]
+    };
+
+    vec![
+        Update::Inputs(vec![
+            channel_port,
+            octave_port,
+            degree_port,
+            count_port,
+            rate_port,
+            velocity_port,
+            duration_port,
+        ]),
+        Update::Notes(notes),
+    ]
+}
+
 fn midi_note(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let channel_port = context.listen("channel", row, col + 1, '0');
     let octave_port = context.listen("octave", row, col + 2, '2');
     let note_port = context.listen("note", row, col + 3, 'C');
     let velocity_port = context.listen("velocity", row, col + 4, 'u');
     let duration_port = context.listen("duration", row, col + 5, '1');
+    let port_port = context.listen("port", row, col + 6, '\0');
+    let release_port = context.listen("release", row, col + 7, '0');
     let note_type = 0;
 
-    let (channel, _) = char_to_base_36(channel_port.value);
+    let channel = midi_channel(channel_port.value);
     let (octave, _) = char_to_base_36(octave_port.value);
     let (note, note_upper) = char_to_base_36(note_port.value);
     let (velocity, _) = char_to_base_36(velocity_port.value);
     let (duration, _) = char_to_base_36(duration_port.value);
+    let (release, _) = char_to_base_36(release_port.value);
 
     let midi_notes = if note >= 10
         && (context.read(row - 1, col) == '*'
         || context.read(row, col - 1) == '*'
         || context.read(row + 1, col) == '*')
     {
-        vec![Note::from_base_36(
-            note_type,
-            channel,
-            0,
-            0,
-            0,
-            octave,
-            note,
-            !note_upper,
-            0,
-            velocity,
-            duration,
-            0,
-            context.tick_time,
-            0,
-        )]
+        vec![Note {
+            port: midi_output_port(context, port_port.value),
+            release_velocity: (release as f32 * (127.0 / 35.0)) as u8,
+            ..Note::from_base_36(
+                note_type,
+                channel,
+                0,
+                0,
+                0,
+                octave,
+                note,
+                !note_upper,
+                0,
+                velocity,
+                duration,
+                0,
+                context.tick_time,
+                0,
+            )
+        }]
     } else {
         vec![]
     };
@@ -882,21 +1149,86 @@ fn midi_note(context: &Context, row: i32, col: i32) -> Vec<Update> {
             note_port,
             velocity_port,
             duration_port,
+            port_port,
+            release_port,
         ]),
         Update::Notes(midi_notes),
     ]
 }
 
+// MIDI has 16 channels, anything above the last one is clamped to it
+fn midi_channel(value: char) -> u8 {
+    let (channel, _) = char_to_base_36(value);
+    channel.min(15)
+}
+
+// an empty port cell sends to the port selected with CTRL-p
+fn midi_output_port(context: &Context, value: char) -> u8 {
+    if value == '\0' {
+        context.midi_port
+    } else {
+        char_to_base_36(value).0
+    }
+}
+
+fn midi_in(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let channel_port = context.listen("channel", row, col + 1, '0');
+
+    let (channel, _) = char_to_base_36(channel_port.value);
+
+    let (octave, note, velocity) = match context
+        .incoming
+        .iter()
+        .rev()
+        .find(|(incoming_channel, _, _)| *incoming_channel == channel)
+    {
+        Some(&(_, note_number, velocity)) => {
+            let (octave, key) = note_to_key(note_number);
+            let velocity = (velocity as f32 * (35.0 / 127.0)).round() as u8;
+            (base_36_to_char(octave, false), key, base_36_to_char(velocity, false))
+        }
+        None => ('.', '.', '.'),
+    };
+
+    vec![
+        Update::Inputs(vec![channel_port]),
+        Update::Outputs(vec![
+            Port::new("octave", row + 1, col, octave),
+            Port::new("note", row + 1, col + 1, note),
+            Port::new("velocity", row + 1, col + 2, velocity),
+        ]),
+    ]
+}
+
+// spells a MIDI note number, written as two base-36 digits, as the octave and key glyph the Midi
+// operator takes, e.g. 1o (60) reads 4 C
+fn note_name(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let high_port = context.listen("high", row, col + 1, '0');
+    let low_port = context.listen("low", row, col + 2, '0');
+
+    let (high, _) = char_to_base_36(high_port.value);
+    let (low, _) = char_to_base_36(low_port.value);
+    let (octave, key) = note_to_key((high as u32 * 36 + low as u32).min(127) as u8);
+
+    vec![
+        Update::Inputs(vec![high_port, low_port]),
+        Update::Outputs(vec![
+            Port::new("octave", row + 1, col, base_36_to_char(octave, false)),
+            Port::new("note", row + 1, col + 1, key),
+        ]),
+    ]
+}
+
 fn midi_cc(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let channel_port = context.listen("channel", row, col + 1, '0');
     let command_port = context.listen("comman", row, col + 2, '0');
     let value_port = context.listen("value", row, col + 3, '0');
 
-    let (channel, _) = char_to_base_36(channel_port.value);
+    let channel = midi_channel(channel_port.value);
     let (command, _) = char_to_base_36(command_port.value);
     let (value, _) = char_to_base_36(value_port.value);
 
-    let channel = channel + 176;
+    let channel = CONTROL_CHANGE_MESSAGE + channel;
 
     let midi_cc = if context.read(row - 1, col) == '*'
         || context.read(row, col - 1) == '*'
@@ -905,16 +1237,10 @@ fn midi_cc(context: &Context, row: i32, col: i32) -> Vec<Update> {
         vec![Note {
             note_type: 3,
             channel,
-            engine: 0,
-            sample: 0,
-            slot: 0,
-            note_number: 0,
             velocity: value,
             duration: 1,
-            reverb: 0,
-            started: false,
             degree: command,
-            speed: 0,
+            ..Default::default()
         }]
     } else {
         vec![]
@@ -926,6 +1252,46 @@ fn midi_cc(context: &Context, row: i32, col: i32) -> Vec<Update> {
     ]
 }
 
+fn pitch_bend(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let channel_port = context.listen("channel", row, col + 1, '0');
+    let bend_port = context.listen("bend", row, col + 2, '\0');
+    let fine_port = context.listen("fine", row, col + 3, '\0');
+
+    let channel = midi_channel(channel_port.value);
+    let (bend, _) = char_to_base_36(bend_port.value);
+    let (fine, _) = char_to_base_36(fine_port.value);
+
+    // an empty bend port centers the wheel, otherwise 0..z spans the full 14 bits.
+    // with a fine value the two ports read as one two-digit base-36 number
+    let bend = if bend_port.value == '\0' {
+        PITCH_BEND_CENTER
+    } else if fine_port.value == '\0' {
+        (bend as u32 * 16383 / 35) as u16
+    } else {
+        ((bend as u32 * 36 + fine as u32) * 16383 / 1295) as u16
+    };
+
+    let pitch_bends = if context.read(row - 1, col) == '*'
+        || context.read(row, col - 1) == '*'
+        || context.read(row + 1, col) == '*'
+    {
+        vec![Note {
+            note_type: 4,
+            channel,
+            note_number: (bend & 0x7F) as u8,
+            velocity: (bend >> 7) as u8,
+            ..Default::default()
+        }]
+    } else {
+        vec![]
+    };
+
+    vec![
+        Update::Inputs(vec![channel_port, bend_port, fine_port]),
+        Update::Notes(pitch_bends),
+    ]
+}
+
 fn synth(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let engine_port = context.listen("engine", row, col + 1, '0');
     let octave_port = context.listen("octave", row, col + 2, '2');
@@ -934,6 +1300,12 @@ fn synth(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let duration_port = context.listen("duration", row, col + 5, '2');
     let reverb_port = context.listen("reverb", row, col + 6, '0');
     let fm_port = context.listen("fm", row, col + 7, '1');
+    let attack_port = context.listen("attack", row, col + 8, '\0');
+    let decay_port = context.listen("decay", row, col + 9, '\0');
+    let sustain_port = context.listen("sustain", row, col + 10, '\0');
+    let release_port = context.listen("release", row, col + 11, '\0');
+    let cutoff_port = context.listen("cutoff", row, col + 12, '\0');
+    let resonance_port = context.listen("resonance", row, col + 13, '0');
 
     let (engine, _) = char_to_base_36(engine_port.value);
     let (octave, _) = char_to_base_36(octave_port.value);
@@ -944,6 +1316,24 @@ fn synth(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let (scale, _) = char_to_base_36(context.global_scale);
     let (reverb, _) = char_to_base_36(reverb_port.value);
     let (fm, _) = char_to_base_36(fm_port.value);
+    // leaving all four envelope ports empty keeps the plucky default envelope
+    let envelope_ports = [&attack_port, &decay_port, &sustain_port, &release_port];
+    let adsr = if envelope_ports.iter().any(|port| port.value != '\0') {
+        Some((
+            char_to_base_36(attack_port.value).0,
+            char_to_base_36(decay_port.value).0,
+            char_to_base_36(sustain_port.value).0,
+            char_to_base_36(release_port.value).0,
+        ))
+    } else {
+        None
+    };
+    // an empty cutoff port leaves the filter open
+    let filter = if cutoff_port.value != '\0' {
+        Some((char_to_base_36(cutoff_port.value).0, char_to_base_36(resonance_port.value).0))
+    } else {
+        None
+    };
     let note_index = (note - 10) % 7;
     let octave_offset = 1 + (note - 10) / 7;
     let note_number = prepare_note(octave, note_upper, degree, scale, octave_offset, note_index as usize);
@@ -956,17 +1346,16 @@ fn synth(context: &Context, row: i32, col: i32) -> Vec<Update> {
     {
         vec![Note {
             note_type: 1,
-            channel: 0,
             engine,
-            sample: 0,
-            slot: 0,
             note_number,
             velocity,
             duration,
-            started: false,
             degree,
             reverb,
             speed: fm,
+            adsr,
+            filter,
+            ..Default::default()
         }]
     } else {
         vec![]
@@ -981,14 +1370,54 @@ fn synth(context: &Context, row: i32, col: i32) -> Vec<Update> {
             duration_port,
             reverb_port,
             fm_port,
+            attack_port,
+            decay_port,
+            sustain_port,
+            release_port,
+            cutoff_port,
+            resonance_port,
         ]),
         Update::Notes(midi_notes),
     ]
 }
 
+fn quantize(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let input_port = context.listen("semitones", row, col - 1, '0');
+
+    let (semitones, _) = char_to_base_36(input_port.value);
+    let (scale, _) = char_to_base_36(context.global_scale);
+    let degree = quantize_to_scale(semitones, scale);
+
+    let out_port = Port::new("out", row + 1, col, base_36_to_char(degree, false));
+
+    vec![
+        Update::Inputs(vec![input_port]),
+        Update::Outputs(vec![out_port]),
+    ]
+}
+
+// snaps semitones above the key root to the nearest degree of the scale; the key itself is added
+// later by whatever plays the degree
+fn quantize_to_scale(semitones: u8, scale: u8) -> u8 {
+    let selected_scale = SCALES.get(scale as usize % 26).expect("invalid scale");
+    let octave = semitones as i32 / 12;
+    let pitch_class = semitones as i32 % 12;
+
+    // the root of the next octave is a candidate too, ties go to the lower degree
+    let (index, _) = selected_scale
+        .iter()
+        .map(|&offset| offset as i32)
+        .chain(std::iter::once(12))
+        .enumerate()
+        .min_by_key(|&(_, offset)| (offset - pitch_class).abs())
+        .expect("empty scale");
+
+    (octave * 7 + index as i32).clamp(0, 35) as u8
+}
+
 fn prepare_note(octave: u8, note_upper: bool, degree: u8, scale: u8, octave_offset: u8, note_index: usize) -> u8 {
     let note_offset = if !note_upper { SHARP_NOTES[note_index] } else { NATURAL_NOTES[note_index] };
-    let octave = octave + octave_offset;
+    let octave = octave as u32 + octave_offset as u32;
     let selected_scale = SCALES.get(scale as usize % 26).expect("invalid scale");
     let scale_offset = match degree {
         0..=6 => 0,
@@ -998,8 +1427,8 @@ fn prepare_note(octave: u8, note_upper: bool, degree: u8, scale: u8, octave_offs
         28..=34 => 48,
         _ => 60,
     } + *selected_scale.get((degree % 7) as usize).expect("invalid degree");
-    let note_number = scale_offset + 12 * octave + note_offset;
-    note_number
+    // octave z with degree z lands far above the MIDI range, clamp instead of wrapping
+    (scale_offset as u32 + 12 * octave + note_offset as u32).min(127) as u8
 }
 
 fn sampler(context: &Context, row: i32, col: i32) -> Vec<Update> {
@@ -1008,7 +1437,12 @@ fn sampler(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let velocity_port = context.listen("velocity", row, col + 3, '9');
     let duration_port = context.listen("duration", row, col + 4, '4');
     let reverb_port = context.listen("reverb", row, col + 5, '0');
-    let speed_port = context.listen("reverb", row, col + 6, '1');
+    let speed_port = context.listen("speed", row, col + 6, '1');
+    let reverse_port = context.listen("reverse", row, col + 7, '\0');
+    let bank_port = context.listen("bank", row, col + 8, '0');
+    let offset_port = context.listen("offset", row, col + 9, '0');
+    let choke_port = context.listen("choke", row, col + 10, '0');
+    let gain_port = context.listen("gain", row, col + 11, 'i');
 
     let (slot, _) = char_to_base_36(slot_port.value);
     let (sample, _) = char_to_base_36(sample_port.value);
@@ -1016,27 +1450,41 @@ fn sampler(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let (duration, _) = char_to_base_36(duration_port.value);
     let (reverb, _) = char_to_base_36(reverb_port.value);
     let (speed, _) = char_to_base_36(speed_port.value);
+    // any value in the reverse port plays the sample backwards
+    let reverse = reverse_port.value != '\0';
+    let (bank, _) = char_to_base_36(bank_port.value);
+    let (offset, _) = char_to_base_36(offset_port.value);
+    let (choke, _) = char_to_base_36(choke_port.value);
+    let (gain, _) = char_to_base_36(gain_port.value);
 
     let sampler_notes = if context.read(row - 1, col) == '*'
         || context.read(row, col - 1) == '*'
         || context.read(row + 1, col) == '*'
     {
-        vec![Note::from_base_36(
-            2,
-            0,
-            0,
-            sample,
-            slot % 4,
-            0,
-            slot,
-            false,
-            0,
-            velocity,
-            duration,
-            reverb,
-            context.tick_time,
-            speed,
-        )]
+        vec![Note {
+            reverse,
+            bank,
+            offset,
+            choke,
+            gain,
+            ..Note::from_base_36(
+                2,
+                0,
+                0,
+                sample,
+                slot % 4,
+                0,
+                // samples have no pitch, any letter keeps the note math in range
+                10,
+                false,
+                0,
+                velocity,
+                duration,
+                reverb,
+                context.tick_time,
+                speed,
+            )
+        }]
     } else {
         vec![]
     };
@@ -1049,6 +1497,11 @@ fn sampler(context: &Context, row: i32, col: i32) -> Vec<Update> {
             duration_port,
             reverb_port,
             speed_port,
+            reverse_port,
+            bank_port,
+            offset_port,
+            choke_port,
+            gain_port,
         ]),
         Update::Notes(sampler_notes),
     ]
@@ -1080,23 +1533,27 @@ fn track(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let (key, _) = char_to_base_36(key_port.value);
     let (len, _) = char_to_base_36(len_port.value);
     let len = len.max(1);
-    let val_port = context.listen("val", row, col + 1 + (key % len) as i32, '\0');
-    let out = val_port.value;
+    // every step is reported as an input, so each cell gets its own label
+    let val_ports: Vec<Port> = (0..(len as i32))
+        .map(|i| context.listen("val", row, col + 1 + i, '\0'))
+        .collect();
+    let out = val_ports[(key % len) as usize].value;
 
     let out_port = Port::new("out", row + 1, col, out);
-    let locks = (0..(len as i32))
-        .map(|i| Port::new("locked", row, col + 1 + i, '\0'))
-        .collect();
+
+    let mut input_ports = vec![key_port, len_port];
+    input_ports.extend(val_ports);
 
     vec![
-        Update::Inputs(vec![key_port, len_port, val_port]),
+        Update::Inputs(input_ports),
         Update::Outputs(vec![out_port]),
-        Update::Locks(locks),
     ]
 }
 
+// locks the cell below so the operator or operand there stays put this tick; the cell is written back
+// unchanged, an empty one stays '.'
 fn halt(context: &Context, row: i32, col: i32) -> Vec<Update> {
-    let output_port = context.listen("out", row + 1, col, '\0');
+    let output_port = context.listen("out", row + 1, col, '.');
     vec![
         Update::Inputs(vec![output_port.clone()]),
         Update::Outputs(vec![output_port.clone()]),
@@ -1272,6 +1729,27 @@ fn lesser(context: &Context, row: i32, col: i32) -> Vec<Update> {
     ]
 }
 
+fn greater(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let a_port = context.listen("a", row, col - 1, '\0');
+    let b_port = context.listen("b", row, col + 1, '\0');
+
+    let out = if a_port.value != '\0' && b_port.value != '\0' {
+        let (a, a_upper) = char_to_base_36(a_port.value);
+        let (b, b_upper) = char_to_base_36(b_port.value);
+        let more = if a > b { a } else { b };
+        base_36_to_char(more, a_upper || b_upper)
+    } else {
+        '\0'
+    };
+
+    let out_port = Port::new("out", row + 1, col, out);
+
+    vec![
+        Update::Inputs(vec![a_port, b_port]),
+        Update::Outputs(vec![out_port]),
+    ]
+}
+
 fn multiply(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let a_port = context.listen("a", row, col - 1, '0');
     let b_port = context.listen("b", row, col + 1, '0');
@@ -1288,6 +1766,22 @@ fn multiply(context: &Context, row: i32, col: i32) -> Vec<Update> {
     ]
 }
 
+fn divide(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let a_port = context.listen("a", row, col - 1, '0');
+    let b_port = context.listen("b", row, col + 1, '0');
+
+    let (a, a_upper) = char_to_base_36(a_port.value);
+    let (b, b_upper) = char_to_base_36(b_port.value);
+    let out = base_36_to_char(a / b.max(1), a_upper || b_upper);
+
+    let out_port = Port::new("out", row + 1, col, out);
+
+    vec![
+        Update::Inputs(vec![a_port, b_port]),
+        Update::Outputs(vec![out_port]),
+    ]
+}
+
 fn read(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let x_port = context.listen("x", row, col - 2, '0');
     let y_port = context.listen("y", row, col - 1, '0');
@@ -1436,8 +1930,9 @@ fn euclid(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let (offset, _) = char_to_base_36(offset_port.value);
     let max = max.max(1);
 
+    let pattern = euclidean_pattern(step as usize, max as usize);
     let mut out_port = context.listen("out", row + 1, col, '\0');
-    if ((step as usize * (context.ticks + offset as usize)) % max as usize) < step as usize {
+    if pattern[(context.ticks + offset as usize) % max as usize] {
         out_port.value = '*';
     }
 
@@ -1447,15 +1942,34 @@ fn euclid(context: &Context, row: i32, col: i32) -> Vec<Update> {
     ]
 }
 
-fn comment(context: &Context, row: i32, col: i32) -> Vec<Update> {
-    let width = context.cols as i32;
-    let mut c = col + 1;
-    for i in c..width {
-        c = i;
-        if context.read(row, c) == '#' {
-            break;
+// bjorklund's algorithm: spreads hits as evenly as possible over steps, e.g. E(3,8) is x..x..x.
+fn euclidean_pattern(hits: usize, steps: usize) -> Vec<bool> {
+    let hits = hits.min(steps);
+    let mut heads: Vec<Vec<bool>> = vec![vec![true]; hits];
+    let mut remainders: Vec<Vec<bool>> = vec![vec![false]; steps - hits];
+
+    // keep appending remainders to heads until at most one remainder is left
+    while remainders.len() > 1 && !heads.is_empty() {
+        let pairs = heads.len().min(remainders.len());
+        let rest = if heads.len() > pairs {
+            heads.split_off(pairs)
+        } else {
+            remainders.split_off(pairs)
+        };
+        for (head, remainder) in heads.iter_mut().zip(remainders.iter()) {
+            head.extend(remainder);
         }
+        remainders = rest;
     }
+
+    heads.into_iter().chain(remainders).flatten().collect()
+}
+
+fn comment(context: &Context, row: i32, col: i32) -> Vec<Update> {
+    let width = context.cols as i32;
+    let c = (col + 1..width)
+        .find(|&c| context.read(row, c) == '#')
+        .unwrap_or((width - 1).max(col + 1));
     let locks = (col..(c + 1))
         .map(|l| Port::new("locked", row, l, '\0'))
         .collect();
@@ -1488,8 +2002,8 @@ fn bernoulli(context: &Context, row: i32, col: i32) -> Vec<Update> {
     let mut out_port_zero = context.listen("out", row + 1, col, '\0');
     let mut out_port_one = context.listen("out2", row + 2, col, '\0');
 
-    let d = Bernoulli::new(probability as f64 / 10.0).expect("invalid probability");
-    let c = d.sample(&mut thread_rng());
+    let d = Bernoulli::new(probability as f64 / 35.0).expect("invalid probability");
+    let c = d.sample(&mut *context.rng.borrow_mut());
 
     if context.read(row - 1, col) == '*'
         || context.read(row, col - 1) == '*'
@@ -1621,6 +2135,7 @@ pub fn snippet_saver(context: &Context, row: i32, col: i32) -> Vec<Update> {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(true)
             .open(file_path)
             .expect("Failed to open file");
 
@@ -1744,6 +2259,7 @@ pub fn snippet_loader(context: &Context, row: i32, col: i32) -> Vec<Update> {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(file_path)
             .expect("Failed to open file");
 
@@ -1777,49 +2293,693 @@ pub fn get_bang_operators(operator_map: &HashMap<String, char>) -> HashMap<char,
     operators
 }
 
+// which glyphs have an operator, built once next to the operator maps so grid_tick can pass over values and
+// empty cells with an array index instead of hashing every one; glyphs outside ASCII like `±` fall back to a list
+pub struct GlyphTable {
+    ascii: [bool; 128],
+    other: Vec<char>,
+}
+
+impl GlyphTable {
+    pub fn new(operators: &HashMap<char, Operator>) -> GlyphTable {
+        let mut table = GlyphTable {
+            ascii: [false; 128],
+            other: Vec::new(),
+        };
+        for &glyph in operators.keys() {
+            match table.ascii.get_mut(glyph as usize) {
+                Some(is_operator) => *is_operator = true,
+                None => table.other.push(glyph),
+            }
+        }
+        table
+    }
+
+    pub fn contains(&self, glyph: char) -> bool {
+        match self.ascii.get(glyph as usize) {
+            Some(&is_operator) => is_operator,
+            None => self.other.contains(&glyph),
+        }
+    }
+}
+
+// a `$` above an operator lets it fire with the chance (0-z) written right of the `$`
+fn chance_gate(context: &mut Context, row: i32, col: i32) -> bool {
+    if context.read(row - 1, col) != CHANCE_GATE {
+        return true;
+    }
+    context.lock_with_name(row - 1, col, "Chance gate".to_string());
+    context.lock_with_name(row - 1, col + 1, "Chance".to_string());
+
+    let (chance, _) = char_to_base_36(context.read(row - 1, col + 1));
+    let chance = chance as f64 / 35.0;
+    context.rng.borrow_mut().gen_bool(chance)
+}
+
 pub fn grid_tick(
     context: &mut Context,
     tick_operators: &HashMap<char, Operator>,
     bang_operators: &HashMap<char, Operator>,
+    tick_glyphs: &GlyphTable,
+    bang_glyphs: &GlyphTable,
     should_redraw_midi: Arc<AtomicBool>,
 ) {
-    let rows = context.rows as i32;
-    let cols = context.cols as i32;
     context.unlock_all();
     context.clear_all_variables();
 
     // clear previous bangs
-    for row in 0..rows {
-        for col in 0..cols {
-            if context.read(row, col) == '*' {
-                context.write(row, col, '.');
+    let bangs: Vec<(i32, i32)> = context
+        .occupied
+        .iter()
+        .copied()
+        .filter(|&(row, col)| context.read(row, col) == '*')
+        .collect();
+    for (row, col) in bangs {
+        context.write(row, col, '.');
+    }
+
+    // held and freshly played midi notes bang their cells, so a quick tap still fires once
+    if let Some((bang_row, bang_col)) = context.midi_bang_cell {
+        let notes: Vec<u8> = context.bang_notes.drain(..).chain(context.held_notes.iter().copied()).collect();
+        for note in notes {
+            context.write(bang_row, bang_col + (note % 12) as i32, '*');
+        }
+    }
+
+    // apply grid operators (which may produce new bangs); only occupied cells can hold one, and the
+    // next cell is looked up after each operator so outputs written further on are still visited
+    let mut cell = context.next_occupied(None);
+    while let Some((row, col)) = cell {
+        let glyph = context.read(row, col);
+        if let Some(operator) = tick_glyphs.contains(glyph).then(|| tick_operators.get(&glyph)).flatten() {
+            let fire = chance_gate(context, row, col);
+            operator.apply(context, row, col, fire);
+            should_redraw_midi.store(true, Ordering::Relaxed);
+        }
+        cell = context.next_occupied(Some((row, col)));
+    }
+
+    // apply bang operators on current bangs
+    let mut cell = context.next_occupied(None);
+    while let Some((row, col)) = cell {
+        let glyph = context.read(row, col);
+        if let Some(operator) = bang_glyphs.contains(glyph).then(|| bang_operators.get(&glyph)).flatten() {
+            if (context.read(row - 1, col) == '*'
+                || context.read(row, col - 1) == '*'
+                || context.read(row + 1, col) == '*')
+                && chance_gate(context, row, col)
+            {
+                operator.apply(context, row, col, true);
             }
         }
+        cell = context.next_occupied(Some((row, col)));
     }
 
-    // apply grid operators (which may produce new bangs)
-    for row in 0..rows {
-        for col in 0..cols {
-            if let Some(operator) = tick_operators.get(&context.read(row, col)) {
-                operator.apply(context, row, col);
-                should_redraw_midi.store(true, Ordering::Relaxed);
+    // variables are rebuilt every tick, the inspector shows this tick's finished set
+    context.variables_snapshot = context.variables.clone();
+
+    // incoming midi notes are only visible for the tick they arrived in
+    context.incoming.clear();
+    context.ticks += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_context(rows: usize, cols: usize) -> Context {
+        Context::new(120, 4, rows, cols, "new", Some(0), "operator_config.txt")
+    }
+
+    // a context with the given lines written in from the top-left corner
+    fn context_with(lines: &[&str]) -> Context {
+        let mut context = empty_context(8, 24);
+        for (row, line) in lines.iter().enumerate() {
+            for (col, glyph) in line.chars().enumerate() {
+                context.write(row as i32, col as i32, glyph);
             }
         }
+        context
     }
 
-    // apply bang operators on current bangs
-    for row in 0..rows {
-        for col in 0..cols {
+    fn tick(context: &mut Context) {
+        let operator_map = read_operator_config("operator_config.txt");
+        let tick_operators = get_tick_operators(&operator_map);
+        let bang_operators = get_bang_operators(&operator_map);
+        grid_tick(
+            context,
+            &tick_operators,
+            &bang_operators,
+            &GlyphTable::new(&tick_operators),
+            &GlyphTable::new(&bang_operators),
+            Arc::new(AtomicBool::new(false)),
+        );
+    }
+
+    #[test]
+    fn sub_wraps_below_zero_with_an_upper_case_result() {
+        let mut context = context_with(&["3B5"]);
+        tick(&mut context);
+        assert_eq!(context.read(1, 1), 'Y');
+    }
+
+    #[test]
+    fn sub_underflow_onto_a_digit_has_no_case() {
+        let mut context = context_with(&["0Bu"]);
+        tick(&mut context);
+        assert_eq!(context.read(1, 1), '6');
+    }
+
+    #[test]
+    fn sub_mode_port_selects_the_absolute_difference() {
+        let mut context = context_with(&["3B5,"]);
+        tick(&mut context);
+        assert_eq!(context.read(1, 1), '2');
+        assert!(context.is_locked(0, 3));
+    }
+
+    #[test]
+    fn sub_leaves_other_glyphs_right_of_b_alone() {
+        // a bang-mode add two cells right of b stays an operator and sub keeps wrapping
+        let mut context = context_with(&["3B5a"]);
+        tick(&mut context);
+        assert_eq!(context.read(1, 1), 'Y');
+        assert!(!context.is_locked(0, 3));
+    }
+
+    #[test]
+    fn delays_with_different_offsets_bang_on_different_ticks() {
+        let mut context = context_with(&["D4.", "....", "....D41"]);
+        let mut bangs = Vec::new();
+        for _ in 0..4 {
+            tick(&mut context);
+            bangs.push((context.read(1, 0), context.read(3, 4)));
+        }
+        assert_eq!(bangs, [('*', '.'), ('.', '*'), ('.', '.'), ('.', '.')]);
+    }
+
+    #[test]
+    fn glyph_table_agrees_with_the_operator_map() {
+        let operator_map = read_operator_config("operator_config.txt");
+        for operators in [get_tick_operators(&operator_map), get_bang_operators(&operator_map)] {
+            let table = GlyphTable::new(&operators);
+            // every ascii glyph, the glyphs outside it that operators use, and a few that nothing uses
+            let glyphs = (0..128u8).map(char::from).chain(operators.keys().copied()).chain(['é', '→', '\u{0}']);
+            for glyph in glyphs {
+                assert_eq!(table.contains(glyph), operators.contains_key(&glyph), "glyph {:?}", glyph);
+            }
+        }
+    }
+
+    // a microbenchmark, run with `cargo test --release glyph_lookup -- --ignored`; it fails with both timings
+    // if the table is slower than the map
+    #[test]
+    #[ignore]
+    fn glyph_lookup_benchmark() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        let operator_map = read_operator_config("operator_config.txt");
+        let operators = get_tick_operators(&operator_map);
+        let table = GlyphTable::new(&operators);
+        // a dense grid: every cell holds a glyph, mostly values with an operator every few cells
+        let glyphs: Vec<char> = "a1.Bc*D2e.3".chars().cycle().take(256 * 256).collect();
+        let passes = 100;
+
+        let start = Instant::now();
+        let mut found = 0;
+        for _ in 0..passes {
+            found += black_box(&glyphs).iter().filter(|glyph| operators.contains_key(glyph)).count();
+        }
+        let map_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut table_found = 0;
+        for _ in 0..passes {
+            table_found += black_box(&glyphs).iter().filter(|&&glyph| table.contains(glyph)).count();
+        }
+        let table_time = start.elapsed();
+
+        assert_eq!(found, table_found);
+        assert!(
+            table_time <= map_time,
+            "{} lookups: HashMap {:?}, GlyphTable {:?}",
+            glyphs.len() * passes,
+            map_time,
+            table_time
+        );
+    }
+
+    #[test]
+    fn sampler_names_every_port() {
+        // the delay bangs on the first tick, right next to the sampler
+        let mut context = context_with(&["D", ".>000000001"]);
+        tick(&mut context);
+        assert_eq!(context.get_port_name(1, 10).map(String::as_str), Some("Offset"));
+        assert!(!context.notes.is_empty());
+        assert!(context.notes.iter().all(|note| note.offset == 1));
+    }
+
+    #[test]
+    fn sampler_names_the_choke_port() {
+        let mut context = context_with(&["D", ".>0000000002"]);
+        tick(&mut context);
+        assert_eq!(context.get_port_name(1, 11).map(String::as_str), Some("Choke"));
+        assert!(!context.notes.is_empty());
+        assert!(context.notes.iter().all(|note| note.choke == 2));
+    }
+
+    #[test]
+    fn sampler_names_the_gain_port() {
+        let mut context = context_with(&["D", ".>0000000000z"]);
+        tick(&mut context);
+        assert_eq!(context.get_port_name(1, 12).map(String::as_str), Some("Gain"));
+        assert!(!context.notes.is_empty());
+        assert!(context.notes.iter().all(|note| note.gain == 35));
+    }
+
+    #[test]
+    fn apply_falls_back_to_the_operator_name() {
+        assert_eq!(Operator::port_name(&["A".to_string()], 0, "Add"), "A");
+        assert_eq!(Operator::port_name(&["A".to_string()], 3, "Add"), "Add");
+    }
+
+    #[test]
+    fn the_default_config_maps_a_to_add() {
+        // no file falls back to the built-in table
+        let operator_map = read_operator_config("no_such_config.txt");
+        assert_eq!(operator_map.get("Add"), Some(&'A'));
+        assert!(get_tick_operators(&operator_map).contains_key(&'A'));
+
+        let mut context = context_with(&["1A2"]);
+        tick(&mut context);
+        assert_eq!(context.read(1, 1), '3');
+        assert_eq!(context.get_port_name(1, 1).map(String::as_str), Some("A+B"));
+    }
+
+    #[test]
+    fn random_accepts_swapped_and_equal_bounds() {
+        let mut swapped = context_with(&["zR0"]);
+        tick(&mut swapped);
+        let (value, _) = char_to_base_36(swapped.read(1, 1));
+        assert!(value < 35);
+
+        // the session seed makes the draw repeatable
+        let mut again = context_with(&["zR0"]);
+        tick(&mut again);
+        assert_eq!(again.read(1, 1), swapped.read(1, 1));
+
+        let mut equal = context_with(&["zRz"]);
+        tick(&mut equal);
+        assert_eq!(equal.read(1, 1), 'z');
+    }
+
+    #[test]
+    fn bernoulli_takes_every_base_36_probability() {
+        for probability in (0..36).map(|value| base_36_to_char(value, false)) {
+            let context = context_with(&[&format!("^{}", probability)]);
+            let updates = bernoulli(&context, 0, 0);
+            let outputs = updates.iter().find_map(|update| match update {
+                Update::Outputs(ports) => Some(ports.len()),
+                _ => None,
+            });
+            assert_eq!(outputs, Some(2), "probability {}", probability);
+        }
+    }
+
+    #[test]
+    fn midi_in_spells_the_latest_note_on_its_channel() {
+        let mut context = context_with(&["&1"]);
+        context.incoming = vec![(1, 62, 64), (0, 64, 100), (1, 60, 127)];
+        tick(&mut context);
+        assert_eq!((context.read(1, 0), context.read(1, 1), context.read(1, 2)), ('4', 'C', 'z'));
+        assert!(context.incoming.is_empty());
+
+        // nothing arrived on channel 1 this tick
+        context.incoming = vec![(0, 64, 100)];
+        tick(&mut context);
+        assert_eq!((context.read(1, 0), context.read(1, 1), context.read(1, 2)), ('.', '.', '.'));
+    }
+
+    #[test]
+    fn bernoulli_at_z_always_bangs_its_second_output() {
+        for _ in 0..8 {
+            let mut context = context_with(&["D", ".^z"]);
+            tick(&mut context);
+            assert_eq!(context.read(3, 1), '*');
+        }
+    }
+
+    fn euclid_string(hits: usize, steps: usize) -> String {
+        euclidean_pattern(hits, steps).iter().map(|&hit| if hit { 'x' } else { '.' }).collect()
+    }
+
+    #[test]
+    fn euclid_spreads_hits_like_bjorklund() {
+        assert_eq!(euclid_string(3, 8), "x..x..x.");
+        assert_eq!(euclid_string(5, 8), "x.xx.xx.");
+    }
+
+    fn output_values(updates: &[Update]) -> Vec<char> {
+        updates
+            .iter()
+            .filter_map(|update| match update {
+                Update::Outputs(ports) => Some(ports.iter().map(|port| port.value)),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn greater_outputs_nothing_when_an_input_is_empty() {
+        assert_eq!(output_values(&greater(&context_with(&["3)"]), 0, 1)), ['\0']);
+        assert_eq!(output_values(&greater(&context_with(&[".)7"]), 0, 1)), ['\0']);
+        assert_eq!(output_values(&greater(&context_with(&["3)7"]), 0, 1)), ['7']);
+        assert_eq!(output_values(&greater(&context_with(&["c)7"]), 0, 1)), ['c']);
+    }
+
+    #[test]
+    fn quantize_maps_4_to_the_third_degree_of_c_major() {
+        let mut context = context_with(&["4|"]);
+        context.global_key = 'C';
+        context.global_scale = '0';
+        tick(&mut context);
+        assert_eq!(context.read(1, 1), '2');
+    }
+
+    fn output_notes(updates: &[Update]) -> Vec<Note> {
+        updates
+            .iter()
+            .filter_map(|update| match update {
+                Update::Notes(notes) => Some(notes.iter().copied()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn pitch_bend_sends_its_channel_and_both_seven_bit_halves() {
+        let notes = output_notes(&pitch_bend(&context_with(&["*", "!3zz"]), 1, 0));
+        assert_eq!(notes.len(), 1);
+        assert_eq!(crate::midi::pitch_bend_message(&notes[0]), [0xE3, 0x7F, 0x7F]);
+
+        // an empty bend port centers the wheel
+        let notes = output_notes(&pitch_bend(&context_with(&["*", "!0"]), 1, 0));
+        assert_eq!(crate::midi::pitch_bend_message(&notes[0]), [0xE0, 0x00, 0x40]);
+
+        // without a bang nothing is sent
+        assert!(output_notes(&pitch_bend(&context_with(&["", "!0z"]), 1, 0)).is_empty());
+    }
+
+    #[test]
+    fn the_sampler_speed_port_is_named_speed() {
+        let mut context = context_with(&[".>"]);
+        tick(&mut context);
+        assert_eq!(context.get_port_name(0, 7).map(String::as_str), Some("Speed"));
+        assert_eq!(context.get_port_name(0, 6).map(String::as_str), Some("Reverb"));
+    }
+
+    #[test]
+    fn synth_envelope_ports_reach_the_voice_envelope() {
+        let notes = output_notes(&synth(&context_with(&["*", "~020920132z5"]), 1, 0));
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].adsr, Some((3, 2, 35, 5)));
+
+        let adsr = crate::synth::Adsr::from_note(&notes[0]).unwrap();
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(adsr.attack, 0.3) && close(adsr.decay, 0.2) && close(adsr.sustain, 1.0));
+        assert!(close(adsr.release, 0.5) && close(adsr.hold, 0.25));
+
+        // empty envelope ports keep the default envelope
+        let notes = output_notes(&synth(&context_with(&["*", "~0209201"]), 1, 0));
+        assert_eq!(notes[0].adsr, None);
+        assert!(crate::synth::Adsr::from_note(&notes[0]).is_none());
+    }
+
+    #[test]
+    fn track_names_each_step_after_its_glyph() {
+        let mut context = context_with(&["ijTabcdefghijklmnopqrs"]);
+        tick(&mut context);
+        assert_eq!(context.read(1, 2), 's');
+        // the 19th step sits at index i
+        assert_eq!(context.get_port_name(0, 21).map(String::as_str), Some("Input I"));
+        assert_eq!(context.get_port_name(0, 3).map(String::as_str), Some("Input 0"));
+        assert_eq!(context.get_port_name(0, 0).map(String::as_str), Some("Step"));
+    }
+
+    #[test]
+    fn a_chance_gate_of_0_never_fires() {
+        let mut context = context_with(&[".$0", "1A2"]);
+        for _ in 0..64 {
+            tick(&mut context);
+            assert_ne!(context.read(2, 1), '3');
+        }
+
+        let mut context = context_with(&[".$z", "1A2"]);
+        tick(&mut context);
+        assert_eq!(context.read(2, 1), '3');
+    }
+
+    #[test]
+    fn arp_walks_up_the_scale_and_wraps() {
+        let mut context = context_with(&["<03031"]);
+        context.global_key = 'C';
+        context.global_scale = '0';
+        let note_numbers: Vec<u8> = (0..6)
+            .map(|ticks| {
+                context.ticks = ticks;
+                let notes = output_notes(&arp(&context, 0, 0));
+                assert_eq!(notes.len(), 1);
+                notes[0].note_number
+            })
+            .collect();
+        let root = note_numbers[0];
+        assert_eq!(note_numbers, [root, root + 2, root + 4, root, root + 2, root + 4]);
+    }
+
+    #[test]
+    fn a_major_chord_stacks_a_third_and_a_fifth_on_the_root() {
+        let notes = output_notes(&chord(&context_with(&["*", "(23C0"]), 1, 0));
+        let note_numbers: Vec<u8> = notes.iter().map(|note| note.note_number).collect();
+        let root = note_numbers[0];
+        assert_eq!(note_numbers, [root, root + 4, root + 7]);
+        assert!(notes.iter().all(|note| note.channel == 2 && note.duration == notes[0].duration));
+
+        let notes = output_notes(&chord(&context_with(&["*", "(23C1"]), 1, 0));
+        let note_numbers: Vec<u8> = notes.iter().map(|note| note.note_number).collect();
+        assert_eq!(note_numbers, [root, root + 3, root + 7]);
+    }
+
+    #[test]
+    fn sampler_port_names_line_up_with_their_columns() {
+        let mut context = context_with(&[">"]);
+        tick(&mut context);
+        let names: Vec<&str> = (1..12)
+            .map(|col| context.get_port_name(0, col).map_or("", String::as_str))
+            .collect();
+        assert_eq!(
+            names,
+            ["Slot", "Sample", "Velocity", "Duration", "Reverb", "Speed", "Reverse", "Bank", "Offset", "Choke", "Gain"]
+        );
+    }
+
+    #[test]
+    fn channel_z_is_clamped_to_the_last_midi_channel() {
+        let notes = output_notes(&midi_note(&context_with(&["*", ":z3C"]), 1, 0));
+        assert_eq!(notes[0].channel, 15);
+        assert_eq!(notes[0].note_on_message()[0], 0x9F);
+
+        // control change carries the channel in its status byte
+        let notes = output_notes(&midi_cc(&context_with(&["*", "!z10"]), 1, 0));
+        assert_eq!(notes[0].channel, 0xBF);
+    }
+
+    #[test]
+    fn octave_and_degree_z_stay_in_the_midi_range() {
+        for note_index in 0..7 {
+            assert!(prepare_note(35, true, 35, 0, 3, note_index) <= 127);
+            assert!(prepare_note(35, false, 35, 25, 3, note_index) <= 127);
+        }
+
+        let mut context = context_with(&["*", "~0zz"]);
+        context.global_key = 'B';
+        let notes = output_notes(&synth(&context, 1, 0));
+        assert_eq!(notes[0].note_number, 127);
+
+        let notes = output_notes(&midi_note(&context_with(&["*", ":0zB"]), 1, 0));
+        assert_eq!(notes[0].note_number, 127);
+    }
+
+    #[test]
+    fn one_bang_fires_every_note_of_the_chord_at_once() {
+        let notes = output_notes(&chord(&context_with(&["*", "(04D0"]), 1, 0));
+        let note_numbers: Vec<u8> = notes.iter().map(|note| note.note_number).collect();
+        let root = note_numbers[0];
+        assert_eq!(note_numbers, [root, root + 4, root + 7]);
+
+        for chord_type in 0..CHORDS.len() as u8 {
+            let row = format!("(04D{}", base_36_to_char(chord_type, false));
+            let notes = output_notes(&chord(&context_with(&["*", &row]), 1, 0));
+            assert_eq!(notes.len(), CHORDS[chord_type as usize].len());
+            assert!(notes.iter().all(|note| note.velocity == notes[0].velocity && note.duration == notes[0].duration));
+            assert!(notes.iter().all(|note| !note.started));
+        }
+    }
+
+    #[test]
+    fn quantize_snaps_out_of_scale_values_to_the_nearest_degree() {
+        // major, ties go down: a semitone above the root to the root, the tritone to the fourth
+        assert_eq!(quantize_to_scale(1, 0), 0);
+        assert_eq!(quantize_to_scale(6, 0), 3);
+        assert_eq!(quantize_to_scale(10, 0), 5);
+        // in-scale values keep their degree, the next octave counts on
+        assert_eq!(quantize_to_scale(11, 0), 6);
+        assert_eq!(quantize_to_scale(13, 0), 7);
+        // 0 is the key root whatever the key, so R or C output feeds straight in
+        let output = |context: &Context| match &quantize(context, 0, 1)[1] {
+            Update::Outputs(ports) => ports[0].value,
+            _ => unreachable!(),
+        };
+        let mut context = context_with(&["0|"]);
+        context.global_key = 'D';
+        assert_eq!(output(&context), '0');
+        context.write(0, 0, '2');
+        assert_eq!(output(&context), '1');
+    }
+
+    #[test]
+    fn euclid_matches_known_patterns_and_rotates() {
+        assert_eq!(euclid_string(4, 16), "x...x...x...x...");
+        assert_eq!(euclid_string(0, 4), "....");
+        assert_eq!(euclid_string(6, 4), "xxxx");
+
+        let bangs = |row: &str| -> String {
+            let mut context = context_with(&[row]);
+            (0..8)
+                .map(|ticks| {
+                    context.ticks = ticks;
+                    if output_values(&euclid(&context, 0, 1)) == ['*'] { 'x' } else { '.' }
+                })
+                .collect()
+        };
+        assert_eq!(bangs("3E8"), "x..x..x.");
+        assert_eq!(bangs("3E81"), "..x..x.x");
+    }
+
+    // grid_tick visiting every cell in reading order, the way it did before the occupied index
+    fn full_scan_tick(
+        context: &mut Context,
+        tick_operators: &HashMap<char, Operator>,
+        bang_operators: &HashMap<char, Operator>,
+    ) {
+        let cells: Vec<(i32, i32)> = (0..context.rows as i32)
+            .flat_map(|row| (0..context.cols as i32).map(move |col| (row, col)))
+            .collect();
+        context.unlock_all();
+        context.clear_all_variables();
+        for &(row, col) in &cells {
+            if context.read(row, col) == '*' {
+                context.write(row, col, '.');
+            }
+        }
+        for &(row, col) in &cells {
+            if let Some(operator) = tick_operators.get(&context.read(row, col)) {
+                let fire = chance_gate(context, row, col);
+                operator.apply(context, row, col, fire);
+            }
+        }
+        for &(row, col) in &cells {
             if let Some(operator) = bang_operators.get(&context.read(row, col)) {
-                if context.read(row - 1, col) == '*'
+                if (context.read(row - 1, col) == '*'
                     || context.read(row, col - 1) == '*'
-                    || context.read(row + 1, col) == '*'
+                    || context.read(row + 1, col) == '*')
+                    && chance_gate(context, row, col)
                 {
-                    operator.apply(context, row, col);
+                    operator.apply(context, row, col, true);
                 }
             }
         }
+        context.variables_snapshot = context.variables.clone();
+        context.incoming.clear();
+        context.ticks += 1;
     }
 
-    context.ticks += 1;
+    #[test]
+    fn ticking_occupied_cells_matches_a_full_scan() {
+        let operator_map = read_operator_config("operator_config.txt");
+        let tick_operators = get_tick_operators(&operator_map);
+        let bang_operators = get_bang_operators(&operator_map);
+        let grid = [
+            "D2......R.f.....",
+            ".E.....C4...aVb.",
+            "..1A2...H.......",
+            "...:03C..3T0123.",
+            "W......*....Vb..",
+            ".#..#..$5.1A2...",
+            "........x.......",
+        ];
+        let mut sparse = context_with(&grid);
+        let mut full = context_with(&grid);
+        for _ in 0..24 {
+            tick(&mut sparse);
+            full_scan_tick(&mut full, &tick_operators, &bang_operators);
+            assert_eq!(sparse.grid, full.grid, "tick {}", sparse.ticks);
+            assert_eq!(sparse.notes.len(), full.notes.len(), "tick {}", sparse.ticks);
+        }
+    }
+
+    #[test]
+    fn a_track_span_is_locked_after_a_tick() {
+        let mut context = context_with(&["13T123.5"]);
+        tick(&mut context);
+        assert!((0..2).all(|col| context.is_locked(0, col)));
+        assert!((3..6).all(|col| context.is_locked(0, col)));
+        // past the length the cells are free again
+        assert!(!context.is_locked(0, 6) && !context.is_locked(0, 7));
+
+        // locks are rebuilt every tick
+        context.write(0, 1, '1');
+        tick(&mut context);
+        assert!(context.is_locked(0, 3) && !context.is_locked(0, 4));
+    }
+
+    #[test]
+    fn an_s_under_a_halt_stays_put() {
+        let mut context = context_with(&["H.", "SS"]);
+        for _ in 0..3 {
+            tick(&mut context);
+        }
+        assert_eq!(context.read(1, 0), 'S');
+        assert!((2..8).all(|row| context.read(row, 0) == '.'));
+        // the one without a halt has moved on
+        assert_eq!(context.read(1, 1), '.');
+        assert_eq!(context.read(4, 1), 'S');
+    }
+
+    #[test]
+    fn a_degree_in_the_note_port_stacks_thirds_of_the_global_scale() {
+        let note_numbers = |context: &Context| -> Vec<u8> {
+            output_notes(&chord(context, 1, 0)).iter().map(|note| note.note_number).collect()
+        };
+        // degree 0 of C major is the C major triad
+        assert_eq!(note_numbers(&context_with(&["*", "(2300"])), note_numbers(&context_with(&["*", "(23C0"])));
+
+        let mut context = context_with(&["*", "(2300"]);
+        context.global_key = 'D';
+        let d_major = note_numbers(&context);
+        assert_eq!(d_major, note_numbers(&context_with(&["*", "(23D0"])));
+        // the second degree of D major is E minor, the minor scale turns the root chord minor
+        context.write(1, 3, '1');
+        let root = d_major[0] + 2;
+        assert_eq!(note_numbers(&context), [root, root + 3, root + 7]);
+        context.write(1, 3, '0');
+        context.global_scale = '1';
+        assert_eq!(note_numbers(&context), [d_major[0], d_major[0] + 3, d_major[0] + 7]);
+
+        // a seventh chord type adds the fourth third
+        context.write(1, 4, '6');
+        assert_eq!(note_numbers(&context).len(), 4);
+    }
 }
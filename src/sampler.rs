@@ -1,15 +1,17 @@
 use std::{
     fs,
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
     thread::{self},
+    time::{Duration, Instant},
 };
 
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     Device, FromSample, SizedSample, StreamConfig,
 };
-use crossbeam::channel::Receiver;
+use crossbeam::{channel::Receiver, select};
+use parking_lot::Mutex;
 use fundsp::{
     hacker::*,
     hacker::{multipass, pan, reverb_stereo, shared, var},
@@ -17,22 +19,27 @@ use fundsp::{
     sequencer::Sequencer64,
 };
 
+use crate::context::Context;
 use crate::note_events::Note;
 use crate::synth::write_data;
 
-#[derive(Debug, Clone, Copy)]
-pub struct SamplerNote {
-    pub sample: u8,
-    pub velocity: u8,
-    pub duration: u64,
-    pub started: bool,
-    pub speed: u8,
-    pub reverb: u8,
+pub const SAMPLES_DIR: &str = "orca/samples";
+pub const DEFAULT_SAMPLER_VOICES: usize = 16;
+
+// a sample still sounding, kept until its end so it can be stopped, stolen or choked
+#[derive(Clone)]
+struct SamplerVoice {
+    sample: u8,
+    slot: u8,
+    choke: u8,
+    id: EventId,
+    end: Instant,
 }
 
+#[allow(dead_code)]
 #[derive(Clone)]
 pub struct SamplerState {
-    id: Vec<Option<EventId>>,
+    voices: Vec<SamplerVoice>,
     sequencer: Sequencer64,
     net: Net64,
     reverb: Shared<f64>,
@@ -40,6 +47,10 @@ pub struct SamplerState {
 
 pub fn sampler_out(
     sampler_note_receiver: Receiver<Vec<Note>>,
+    sample_reload_receiver: Receiver<()>,
+    master_volume: Shared<f64>,
+    voice_limit: usize,
+    sampler_context_arc: Arc<Mutex<Context>>,
 ) {
     let host = cpal::default_host();
     let device = host
@@ -52,21 +63,37 @@ pub fn sampler_out(
             device,
             config.into(),
             sampler_note_receiver,
+            sample_reload_receiver,
+            master_volume,
+            voice_limit,
+            sampler_context_arc,
         ),
         cpal::SampleFormat::F64 => run::<f64>(
             device,
             config.into(),
             sampler_note_receiver,
+            sample_reload_receiver,
+            master_volume,
+            voice_limit,
+            sampler_context_arc,
         ),
         cpal::SampleFormat::I16 => run::<i16>(
             device,
             config.into(),
             sampler_note_receiver,
+            sample_reload_receiver,
+            master_volume,
+            voice_limit,
+            sampler_context_arc,
         ),
         cpal::SampleFormat::U16 => run::<u16>(
             device,
             config.into(),
             sampler_note_receiver,
+            sample_reload_receiver,
+            master_volume,
+            voice_limit,
+            sampler_context_arc,
         ),
         _ => panic!("Unsupported format"),
     }
@@ -77,6 +104,10 @@ pub fn run<T>(
     device: Device,
     config: StreamConfig,
     sampler_note_receiver: Receiver<Vec<Note>>,
+    sample_reload_receiver: Receiver<()>,
+    master_volume: Shared<f64>,
+    voice_limit: usize,
+    sampler_context_arc: Arc<Mutex<Context>>,
 ) where
     T: SizedSample + FromSample<f64>,
 {
@@ -94,7 +125,9 @@ pub fn run<T>(
 
         net = net
             >> ((1.0 - var(&reverb) >> follow(0.01) >> split()) * multipass()
-            & (var(&reverb) >> follow(0.01) >> split()) * reverb_stereo(2.0, 2.0)) >> limiter_stereo((0.005, 0.2));
+            & (var(&reverb) >> follow(0.01) >> split()) * reverb_stereo(2.0, 2.0))
+            >> (var(&master_volume) >> follow(0.01) >> split::<U2>()) * multipass::<U2>()
+            >> limiter_stereo((0.005, 0.2));
 
         net.set_sample_rate(sample_rate);
 
@@ -104,12 +137,11 @@ pub fn run<T>(
 
         let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
         let mut sampler_state = SamplerState {
-            id: Vec::new(),
+            voices: Vec::new(),
             sequencer,
             net,
             reverb,
         };
-        sampler_state.id.resize(4, None);
 
         let stream = device
             .build_output_stream(
@@ -123,80 +155,212 @@ pub fn run<T>(
             .expect("failed to build output stream");
         stream.play().expect("failed to play stream");
 
-        let dir_path = Path::new("orca/samples");
+        let dir_path = Path::new(SAMPLES_DIR);
 
         // read the directory
         if !dir_path.exists() {
             fs::create_dir_all(dir_path).expect("Unable to create directory");
         }
-        let entries = fs::read_dir(dir_path).expect("Unable to list files in directory");
-
-        // filter for .wav files and load them
-        let waves: Vec<Arc<Wave64>> = entries
-            .filter_map(Result::ok)
-            .filter(|entry| {
-                // filter for .wav files
-                let path = entry.path();
-                path.is_file() && path.extension().map_or(false, |ext| ext == "wav")
-            })
-            .map(|entry| {
-                // load each .wav file
-                let path = entry.path();
-                let wave =
-                    Arc::new(Wave64::load(path.to_str().expect("Failed to load path")).expect("Failed to load track"));
-                wave
-            })
-            .collect();
+        let loaded = load_banks(dir_path);
+        publish_banks(&sampler_context_arc, &loaded, false);
+        let mut banks = loaded.waves;
 
         let wave_noise = Arc::new(Wave64::render(44100.0, 0.01, &mut (pink())));
+        let mut reversed_banks = reverse_banks(&banks);
 
         loop {
+            let mut notes = select! {
+                recv(sampler_note_receiver) -> notes => notes.expect("Failed to receive note"),
+                // voices still playing keep their own Arc to the old waves
+                recv(sample_reload_receiver) -> _ => {
+                    let loaded = load_banks(dir_path);
+                    publish_banks(&sampler_context_arc, &loaded, true);
+                    banks = loaded.waves;
+                    reversed_banks = reverse_banks(&banks);
+                    continue;
+                }
+            };
 
-            let mut notes = sampler_note_receiver.recv().expect("Failed to receive note");
-            notes.iter_mut().enumerate().for_each(|(i, note)| {
-                if note.started && note.duration == 0 {
-                    if let Some(id) = sampler_state.id[i] {
-                        sampler_state.sequencer.edit_relative(id, 0.02, 0.02);
-                        sampler_state.id[i] = None;
-                    }
+            // forget voices that already ended, and fade out the ones whose note was stopped
+            let now = Instant::now();
+            sampler_state.voices.retain(|voice| voice.end > now);
+            for note in notes.iter().filter(|note| note.started && note.duration == 0) {
+                let stopped = |voice: &SamplerVoice| voice.sample == note.sample && voice.slot == note.slot;
+                for voice in sampler_state.voices.iter().filter(|voice| stopped(voice)) {
+                    sampler_state.sequencer.edit_relative(voice.id, 0.02, 0.02);
                 }
-                if !note.started && sampler_state.id[i].is_none() {
-                    note.started = true;
-                    sampler_state.reverb.set(note.reverb as f64 * 0.0277);
-
-                    let waveform = match note.slot {
-                        0 => play_wave(note, waves.clone(), wave_noise.clone()),
-                        1 => play_wave(note, waves.clone(), wave_noise.clone()),
-                        2 => play_wave(note, waves.clone(), wave_noise.clone()),
-                        3 => play_wave(note, waves.clone(), wave_noise.clone()),
-                        4 => play_wave(note, waves.clone(), wave_noise.clone()),
-                        _ => play_wave(note, waves.clone(), wave_noise.clone()),
-                    };
-
-                    sampler_state.id[i] = Some(sampler_state.sequencer.push_relative(
-                        0.0,
-                        f64::INFINITY,
-                        Fade::Smooth,
-                        0.0,
-                        0.2,
-                        Box::new(waveform),
-                    ));
-                    if let Some(id) = sampler_state.id[i] {
-                        // sampler_state.id[i] = None;
-                        sampler_state.sequencer.edit_relative(
-                            id,
-                            note.duration as f64 * 0.001,
-                            0.2,
-                        );
-                        sampler_state.id[i] = None;
-                    }
+                sampler_state.voices.retain(|voice| !stopped(voice));
+            }
+
+            notes.iter_mut().filter(|note| !note.started).for_each(|note| {
+                for id in make_room(&mut sampler_state.voices, note, voice_limit) {
+                    sampler_state.sequencer.edit_relative(id, 0.02, 0.02);
                 }
+
+                note.started = true;
+                sampler_state.reverb.set(note.reverb as f64 * 0.0277);
+
+                let banks = if note.reverse { &reversed_banks } else { &banks };
+                let waves = banks.get(note.bank as usize % banks.len()).expect("no sample banks");
+                let waveform = play_wave(note, waves.clone(), wave_noise.clone());
+
+                let id = sampler_state.sequencer.push_relative(
+                    0.0,
+                    f64::INFINITY,
+                    Fade::Smooth,
+                    0.0,
+                    0.2,
+                    Box::new(waveform),
+                );
+                sampler_state.sequencer.edit_relative(id, note.duration as f64 * 0.001, 0.2);
+                sampler_state.voices.push(SamplerVoice {
+                    sample: note.sample,
+                    slot: note.slot,
+                    choke: note.choke,
+                    id,
+                    end: now + Duration::from_millis(note.duration),
+                });
             });
         }
     });
 }
 
+// takes out the voices a new note ends and hands back their ids so they can fade out
+fn make_room(voices: &mut Vec<SamplerVoice>, note: &Note, voice_limit: usize) -> Vec<EventId> {
+    let mut ended = Vec::new();
+
+    // every slot has its own voices, kept in start order so the first one in a slot is its oldest
+    while voices.iter().filter(|voice| voice.slot == note.slot).count() >= voice_limit {
+        let Some(oldest) = voices.iter().position(|voice| voice.slot == note.slot) else {
+            break;
+        };
+        ended.push(voices.remove(oldest).id);
+    }
+
+    // choke groups reach across slots, so an open and a closed hat can sit on different ones
+    if note.choke > 0 {
+        ended.extend(voices.iter().filter(|voice| voice.choke == note.choke).map(|voice| voice.id));
+        voices.retain(|voice| voice.choke != note.choke);
+    }
+    ended
+}
+
+fn reverse_banks(banks: &[Vec<Arc<Wave64>>]) -> Vec<Vec<Arc<Wave64>>> {
+    banks
+        .iter()
+        .map(|waves| waves.iter().map(|wave| Arc::new(reversed(wave))).collect())
+        .collect()
+}
+
+// a copy of the wave with every channel played back to front
+fn reversed(wave: &Wave64) -> Wave64 {
+    let mut reversed = Wave64::new(0, wave.sample_rate());
+    for channel in 0..wave.channels() {
+        let mut samples = wave.channel(channel).clone();
+        samples.reverse();
+        reversed.push_channel(&samples);
+    }
+    reversed
+}
+
+// bank 0 holds the wavs directly in the samples folder, then every subfolder is a bank.
+// everything is sorted by path so an index always points at the same file
+pub fn bank_paths(dir_path: &Path) -> Vec<Vec<PathBuf>> {
+    let mut folders: Vec<PathBuf> = fs::read_dir(dir_path)
+        .expect("Unable to list files in directory")
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    folders.sort();
+
+    std::iter::once(dir_path.to_path_buf())
+        .chain(folders)
+        .map(|folder| wav_paths(&folder))
+        .collect()
+}
+
+fn wav_paths(dir_path: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir_path)
+        .expect("Unable to list files in directory")
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        // filter for .wav files
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "wav"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+// file names per bank in sample index order, for showing which file a sample port picks
+pub fn sample_names(dir_path: &Path) -> Vec<Vec<String>> {
+    if !dir_path.exists() {
+        return Vec::new();
+    }
+    bank_paths(dir_path)
+        .iter()
+        .map(|paths| paths.iter().map(|path| file_name(path)).collect())
+        .collect()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string())
+}
+
+// the waves of every bank with their file names; files that can't be read are skipped so one broken wav
+// can't take the sampler down, and listed to be reported
+struct Banks {
+    waves: Vec<Vec<Arc<Wave64>>>,
+    names: Vec<Vec<String>>,
+    skipped: Vec<String>,
+}
+
+fn load_banks(dir_path: &Path) -> Banks {
+    let mut banks = Banks {
+        waves: Vec::new(),
+        names: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for paths in bank_paths(dir_path) {
+        let (mut waves, mut names) = (Vec::new(), Vec::new());
+        for path in paths {
+            match Wave64::load(&path) {
+                Ok(wave) => {
+                    waves.push(Arc::new(wave));
+                    names.push(file_name(&path));
+                }
+                Err(_) => banks.skipped.push(file_name(&path)),
+            }
+        }
+        banks.waves.push(waves);
+        banks.names.push(names);
+    }
+    banks
+}
+
+// the sample port indexes the loaded waves, so the status line has to show their names rather than the
+// folder listing
+fn publish_banks(context_arc: &Arc<Mutex<Context>>, banks: &Banks, reloaded: bool) {
+    let count: usize = banks.names.iter().map(Vec::len).sum();
+    let message = match (reloaded, banks.skipped.is_empty()) {
+        (true, true) => Some(format!("reloaded {} samples", count)),
+        (true, false) => Some(format!("reloaded {} samples, skipped unreadable {}", count, banks.skipped.join(", "))),
+        (false, true) => None,
+        (false, false) => Some(format!("skipped unreadable samples: {}", banks.skipped.join(", "))),
+    };
+    let mut context = context_arc.lock();
+    context.sample_names = banks.names.clone();
+    if let Some(message) = message {
+        context.status_message = Some((message, Instant::now()));
+    }
+}
+
 fn play_wave(note: &Note, waves: Vec<Arc<Wave64>>, wave_noise: Arc<Wave64>) -> Net64 {
+    // an empty bank plays noise
+    let wave = waves
+        .get((note.sample as usize).checked_rem(waves.len()).unwrap_or(0))
+        .unwrap_or(&wave_noise);
+    let wave = if note.offset > 0 { Arc::new(skipped(wave, note.offset)) } else { wave.clone() };
     Net64::wrap(Box::new(
         (lfo(|t| xerp11(1.0, 1.0, spline_noise(1, t))) * {
             if note.speed as f64 >= 9.0 {
@@ -204,14 +368,206 @@ fn play_wave(note: &Note, waves: Vec<Arc<Wave64>>, wave_noise: Arc<Wave64>) -> N
             } else {
                 note.speed as f64
             }
-        }) >> resample(wave64(
-            waves
-                .clone()
-                .get(note.sample as usize % (waves.len() + 1) % 35)
-                .unwrap_or(&wave_noise),
-            0,
-            None,
-        )),
+        }) >> (resample(wave64(&wave, 0, None)) * gain(note)),
     ))
 }
 
+// 0 mutes, i (18) plays the sample as recorded and z (35) almost doubles it
+fn gain(note: &Note) -> f64 {
+    (note.gain as f64 / 18.0).min(35.0 / 18.0)
+}
+
+// a copy of the wave starting offset 36ths of the way in, reversed waves skip from their end
+fn skipped(wave: &Wave64, offset: u8) -> Wave64 {
+    let start = wave.len() * offset as usize / 36;
+    let mut skipped = Wave64::new(0, wave.sample_rate());
+    for channel in 0..wave.channels() {
+        skipped.push_channel(&wave.channel(channel)[start..]);
+    }
+    skipped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_banks_skips_unreadable_wavs() {
+        let dir = std::env::temp_dir().join(format!("orca-rs-samples-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Wave64::render(44100.0, 0.01, &mut (pink())).save_wav16(dir.join("good.wav")).unwrap();
+        fs::write(dir.join("bad.wav"), "not a wav").unwrap();
+
+        let banks = load_banks(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(banks.waves[0].len(), 1);
+        assert_eq!(banks.names, [["good.wav"]]);
+        assert_eq!(banks.skipped, ["bad.wav"]);
+    }
+
+    #[test]
+    fn gain_scales_the_level_and_clamps_at_z() {
+        let level = |gain| super::gain(&Note { gain, ..Default::default() });
+        assert_eq!(level(0), 0.0);
+        assert_eq!(level(9), 0.5);
+        assert_eq!(level(18), 1.0);
+        assert_eq!(level(35), 35.0 / 18.0);
+        assert_eq!(level(200), 35.0 / 18.0);
+    }
+
+    #[test]
+    fn a_reversed_wave_is_the_forward_wave_back_to_front() {
+        let mut wave = Wave64::new(0, 44100.0);
+        wave.push_channel(&[0.0, 0.1, 0.2, 0.3]);
+        wave.push_channel(&[1.0, -1.0, 0.5, -0.5]);
+
+        let reversed = reversed(&wave);
+        assert_eq!(reversed.sample_rate(), 44100.0);
+        for channel in 0..2 {
+            let mut forward = wave.channel(channel).clone();
+            forward.reverse();
+            assert_eq!(*reversed.channel(channel), forward);
+        }
+    }
+
+    #[test]
+    fn banks_and_samples_keep_their_indices() {
+        let dir = std::env::temp_dir().join(format!("orca-rs-banks-{}", std::process::id()));
+        for folder in ["zz_percussion", "a_drums"] {
+            fs::create_dir_all(dir.join(folder)).unwrap();
+        }
+        for file in ["snare.wav", "kick.wav", "a_drums/hat.wav", "a_drums/clap.wav", "zz_percussion/conga.wav"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(dir.join("a_drums/notes.txt"), "").unwrap();
+
+        let banks = bank_paths(&dir);
+        let names = sample_names(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(banks[1][1], dir.join("a_drums/hat.wav"));
+        assert_eq!(names, [vec!["kick.wav", "snare.wav"], vec!["clap.wav", "hat.wav"], vec!["conga.wav"]]);
+    }
+
+    #[test]
+    fn a_reload_picks_up_a_newly_added_sample() {
+        let dir = std::env::temp_dir().join(format!("orca-rs-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wave = Wave64::render(44100.0, 0.01, &mut (pink()));
+        wave.save_wav16(dir.join("b_kick.wav")).unwrap();
+        let context_arc = Arc::new(Mutex::new(Context::new(120, 4, 8, 8, "new", Some(0), "operator_config.txt")));
+        publish_banks(&context_arc, &load_banks(&dir), false);
+        assert_eq!(context_arc.lock().sample_names, [["b_kick.wav"]]);
+
+        wave.save_wav16(dir.join("a_snare.wav")).unwrap();
+        let reloaded = load_banks(&dir);
+        publish_banks(&context_arc, &reloaded, true);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reloaded.waves[0].len(), 2);
+        let context = context_arc.lock();
+        assert_eq!(context.sample_names, [["a_snare.wav", "b_kick.wav"]]);
+        assert_eq!(context.status_message.as_ref().map(|(message, _)| message.as_str()), Some("reloaded 2 samples"));
+    }
+
+    #[test]
+    fn sample_numbers_follow_the_file_names() {
+        let dir = std::env::temp_dir().join(format!("orca-rs-order-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wave = Wave64::render(44100.0, 0.01, &mut (pink()));
+        for name in ["10_hat.wav", "01_snare.wav", "00_kick.wav"] {
+            wave.save_wav16(dir.join(name)).unwrap();
+        }
+
+        let first = load_banks(&dir);
+        let second = load_banks(&dir);
+        let listed = sample_names(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(first.names, [["00_kick.wav", "01_snare.wav", "10_hat.wav"]]);
+        assert_eq!(second.names, first.names);
+        // the names shown for a sample port are the ones it plays
+        assert_eq!(listed, first.names);
+    }
+
+    #[test]
+    fn a_reversed_sample_with_an_offset_skips_in_from_its_end() {
+        let mut wave = Wave64::new(0, 44100.0);
+        wave.push_channel(&(0..36).map(f64::from).collect::<Vec<_>>());
+        let banks = reverse_banks(&[vec![Arc::new(wave)]]);
+        let reversed = &banks[0][0];
+        assert_eq!(reversed.at(0, 0), 35.0);
+        assert_eq!(reversed.at(0, 35), 0.0);
+
+        // a quarter of the way in from the end
+        let skipped = skipped(reversed, 9);
+        assert_eq!(skipped.len(), 27);
+        assert_eq!(skipped.at(0, 0), 26.0);
+    }
+
+    // starts a voice for note the way the sampler thread does, handing back the ids of the voices it ended
+    fn trigger(voices: &mut Vec<SamplerVoice>, note: &Note, voice_limit: usize) -> Vec<EventId> {
+        let ended = make_room(voices, note, voice_limit);
+        voices.push(SamplerVoice {
+            sample: note.sample,
+            slot: note.slot,
+            choke: note.choke,
+            id: EventId::new(),
+            end: Instant::now() + Duration::from_secs(1),
+        });
+        ended
+    }
+
+    fn samples(voices: &[SamplerVoice]) -> Vec<u8> {
+        voices.iter().map(|voice| voice.sample).collect()
+    }
+
+    #[test]
+    fn more_than_4_triggers_on_a_slot_all_sound() {
+        let mut voices = Vec::new();
+        for sample in 0..8 {
+            assert!(trigger(&mut voices, &Note { sample, ..Default::default() }, DEFAULT_SAMPLER_VOICES).is_empty());
+        }
+        assert_eq!(samples(&voices), [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // past the limit the oldest voice of the slot is stolen
+        let mut voices = Vec::new();
+        for sample in 0..6 {
+            trigger(&mut voices, &Note { sample, ..Default::default() }, 4);
+        }
+        assert_eq!(samples(&voices), [2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn a_choke_group_stops_the_voice_it_already_has() {
+        let mut voices = Vec::new();
+        let open_hat = Note { sample: 1, slot: 0, choke: 1, ..Default::default() };
+        let closed_hat = Note { sample: 2, slot: 3, choke: 1, ..Default::default() };
+        let kick = Note { sample: 0, slot: 0, ..Default::default() };
+        trigger(&mut voices, &open_hat, DEFAULT_SAMPLER_VOICES);
+        let open_hat_id = voices[0].id;
+        trigger(&mut voices, &kick, DEFAULT_SAMPLER_VOICES);
+
+        // the group reaches across slots and leaves voices outside it alone
+        assert_eq!(trigger(&mut voices, &closed_hat, DEFAULT_SAMPLER_VOICES), [open_hat_id]);
+        assert_eq!(samples(&voices), [0, 2]);
+        let closed_hat_id = voices[1].id;
+        assert_eq!(trigger(&mut voices, &closed_hat, DEFAULT_SAMPLER_VOICES), [closed_hat_id]);
+        assert_eq!(samples(&voices), [0, 2]);
+    }
+
+    #[test]
+    fn notes_on_distinct_slots_dont_evict_each_other() {
+        let mut voices = Vec::new();
+        for slot in 0..4 {
+            for sample in 0..2 {
+                let note = Note { slot, sample: slot * 10 + sample, ..Default::default() };
+                assert!(trigger(&mut voices, &note, 2).is_empty());
+            }
+        }
+        assert_eq!(samples(&voices), [0, 1, 10, 11, 20, 21, 30, 31]);
+
+        // a full slot only steals from itself
+        let slot_0_oldest = voices[0].id;
+        assert_eq!(trigger(&mut voices, &Note { slot: 0, sample: 2, ..Default::default() }, 2), [slot_0_oldest]);
+        assert_eq!(samples(&voices), [1, 10, 11, 20, 21, 30, 31, 2]);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     thread::{self},
+    time::{Duration, Instant},
 };
 
 use cpal::{
@@ -19,10 +20,65 @@ use fundsp::{
 
 use crate::note_events::Note;
 
+// the oldest sounding voice is stolen once this many are playing
+pub const DEFAULT_VOICES: usize = 32;
+
+// envelope times in seconds, with the release starting once the note is held for its duration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adsr {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+    pub hold: f64,
+}
+
+impl Adsr {
+    // base-36 attack, decay and release step by 0.1s, sustain spans 0..z as a level
+    pub fn from_note(note: &Note) -> Option<Adsr> {
+        note.adsr.map(|(attack, decay, sustain, release)| Adsr {
+            attack: attack as f64 * 0.1,
+            decay: decay as f64 * 0.1,
+            sustain: sustain as f64 / 35.0,
+            release: release as f64 * 0.1,
+            hold: note.duration as f64 * 0.001,
+        })
+    }
+
+    pub fn level(&self, t: f64) -> f64 {
+        let held = if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        };
+        if t < self.hold {
+            held
+        } else if self.release > 0.0 {
+            // release from wherever the envelope was when the note ended
+            self.level_at_hold() * (1.0 - (t - self.hold) / self.release).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    fn level_at_hold(&self) -> f64 {
+        Adsr { hold: f64::INFINITY, ..*self }.level(self.hold)
+    }
+}
+
+fn envelope_level(adsr: Option<Adsr>, t: f64) -> f64 {
+    match adsr {
+        Some(adsr) => adsr.level(t),
+        None => exp(-t * 10.0),
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct SynthState {
-    id: Vec<Option<EventId>>,
+    voices: Vec<(u8, EventId, Instant)>,
     sequencer: Sequencer64,
     net: Net64,
     reverb: Shared<f64>,
@@ -30,6 +86,8 @@ pub struct SynthState {
 
 pub fn synth_out(
     synth_note_receiver: Receiver<Vec<Note>>,
+    master_volume: Shared<f64>,
+    voice_limit: usize,
 ) {
     let host = cpal::default_host();
     let device = host
@@ -42,21 +100,29 @@ pub fn synth_out(
             device,
             config.into(),
             synth_note_receiver,
+            master_volume,
+            voice_limit,
         ),
         cpal::SampleFormat::F64 => run::<f64>(
             device,
             config.into(),
             synth_note_receiver,
+            master_volume,
+            voice_limit,
         ),
         cpal::SampleFormat::I16 => run::<i16>(
             device,
             config.into(),
             synth_note_receiver,
+            master_volume,
+            voice_limit,
         ),
         cpal::SampleFormat::U16 => run::<u16>(
             device,
             config.into(),
             synth_note_receiver,
+            master_volume,
+            voice_limit,
         ),
         _ => panic!("Unsupported format"),
     }
@@ -67,6 +133,8 @@ pub fn run<T>(
     device: Device,
     config: StreamConfig,
     synth_note_receiver: Receiver<Vec<Note>>,
+    master_volume: Shared<f64>,
+    voice_limit: usize,
 ) where
     T: SizedSample + FromSample<f64>,
 {
@@ -84,6 +152,7 @@ pub fn run<T>(
         net = net
             >> ((1.0 - var(&reverb) >> follow(0.01) >> split()) * multipass()
             & (var(&reverb) >> follow(0.01) >> split()) * reverb_stereo(2.0, 2.0));
+        net = net >> ((var(&master_volume) >> follow(0.01) >> split::<U2>()) * multipass::<U2>());
         net = net >> (declick() | declick()) >> (dcblock() | dcblock()) >> (limiter((0.0, 0.1)) | limiter((0.0, 0.1)));
         net.set_sample_rate(sample_rate);
 
@@ -94,12 +163,11 @@ pub fn run<T>(
         let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
 
         let mut synth_state = SynthState {
-            id: Vec::new(),
+            voices: Vec::new(),
             sequencer,
             net,
             reverb,
         };
-        synth_state.id.resize(36, None);
 
         let stream = device
             .build_output_stream(
@@ -116,33 +184,49 @@ pub fn run<T>(
 
         loop {
             let mut notes = synth_note_receiver.recv().expect("failed to receive note");
-            notes.iter_mut().enumerate().for_each(|(i, note)| if !note.started && synth_state.id[i].is_none() {
+
+            let now = Instant::now();
+            for id in release_voices(&mut synth_state.voices, &notes, now) {
+                synth_state.sequencer.edit_relative(id, 0.02, 0.02);
+            }
+
+            notes.iter_mut().filter(|note| !note.started).for_each(|note| {
+                for (_, id, _) in steal_oldest(&mut synth_state.voices, voice_limit) {
+                    synth_state.sequencer.edit_relative(id, 0.02, 0.02);
+                }
+
                 let pitch = midi_hz(note.note_number as f64);
+                let adsr = Adsr::from_note(note);
+                let release = adsr.map_or(0.0, |adsr| adsr.release);
                 synth_state.reverb.set(note.reverb as f64 * 0.0277);
                 let waveform = match note.engine {
                     0 => Net64::wrap(Box::new(oversample(sine_synth(
                         pitch,
                         note.speed as f64,
                         note.velocity as f64 * 0.0076,
-                        sine_hz(pitch)
+                        sine_hz(pitch),
+                        adsr,
                     )))),
                     1 => Net64::wrap(Box::new(oversample(saw_synth(
                         pitch,
                         note.speed as f64,
                         note.velocity as f64 * 0.0076,
-                        sine_hz(pitch)
+                        sine_hz(pitch),
+                        adsr,
                     )))),
                     2 => Net64::wrap(Box::new(oversample(tri_synth(
                         pitch,
                         note.speed as f64,
                         note.velocity as f64 * 0.0076,
-                        sine_hz(pitch)
+                        sine_hz(pitch),
+                        adsr,
                     )))),
                     3 => Net64::wrap(Box::new(oversample(square_synth(
                         pitch,
                         note.speed as f64,
                         note.velocity as f64 * 0.0076,
-                        sine_hz(pitch)
+                        sine_hz(pitch),
+                        adsr,
                     )))),
                     _ => {
                         Net64::wrap(Box::new(
@@ -155,22 +239,53 @@ pub fn run<T>(
                     }
                 };
 
-                synth_state.id[i] = Some(synth_state.sequencer.push_relative(
+                // the cutoff sweeps 100 Hz to 8 kHz on a log scale, resonance raises q from 0.5 to about 10
+                let waveform = match note.filter {
+                    Some((cutoff, resonance)) => {
+                        let cutoff_hz = 100.0 * 80.0_f64.powf(cutoff as f64 / 35.0);
+                        let q = 0.5 + resonance as f64 * 0.27;
+                        waveform >> Net64::wrap(Box::new(lowpass_hz(cutoff_hz, q)))
+                    }
+                    None => waveform,
+                };
+
+                // an explicit envelope shapes the release itself, so the voice only needs a short fade
+                let fade_out = if adsr.is_some() { 0.01 } else { note.duration as f64 * 0.001 };
+                let id = synth_state.sequencer.push_relative(
                     0.0,
-                    note.duration as f64 * 0.001,
+                    note.duration as f64 * 0.001 + release,
                     Fade::Smooth,
                     0.01,
-                    note.duration as f64 * 0.001,
+                    fade_out,
                     Box::new(waveform),
+                );
+                synth_state.voices.push((
+                    note.note_number,
+                    id,
+                    now + Duration::from_millis(note.duration) + Duration::from_secs_f64(release),
                 ));
-                if let Some(_id) = synth_state.id[i] {
-                    synth_state.id[i] = None;
-                }
             });
         }
     });
 }
 
+// forgets voices that already ended and hands back the ones whose note was stopped, so they can fade out
+fn release_voices(voices: &mut Vec<(u8, EventId, Instant)>, notes: &[Note], now: Instant) -> Vec<EventId> {
+    voices.retain(|&(_, _, end)| end > now);
+    let mut released = Vec::new();
+    for note in notes.iter().filter(|note| note.started && note.duration == 0) {
+        released.extend(voices.iter().filter(|voice| voice.0 == note.note_number).map(|&(_, id, _)| id));
+        voices.retain(|voice| voice.0 != note.note_number);
+    }
+    released
+}
+
+// makes room for one more voice; voices are kept in start order, so the first ones are the oldest
+fn steal_oldest<T>(voices: &mut Vec<T>, voice_limit: usize) -> std::vec::Drain<'_, T> {
+    let stolen = Ord::min((voices.len() + 1).saturating_sub(voice_limit), voices.len());
+    voices.drain(..stolen)
+}
+
 pub fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> (f64, f64))
     where
         T: SizedSample + FromSample<f64>,
@@ -214,10 +329,11 @@ pub fn sine_synth(
     fm: f64,
     velocity: f64,
     waveform: An<Pipe<f64, Constant<U1, f64>, Sine<f64>>>,
+    adsr: Option<Adsr>,
 ) -> An<impl AudioNode<Sample=f64, Inputs=U0, Outputs=U1>> {
-    let wave = waveform * ((pitch * 0.75) * fm) * 1.0 >> sine();
-    let env = lfo(|t| exp(-t * 10.0));
-    (wave * velocity) * env >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
+    let wave = (waveform * ((pitch * 0.75) * fm) * 1.0) >> sine();
+    let env = lfo(move |t| envelope_level(adsr, t));
+    ((wave * velocity) * env) >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
 }
 
 pub fn saw_synth(
@@ -225,10 +341,11 @@ pub fn saw_synth(
     fm: f64,
     velocity: f64,
     waveform: An<Pipe<f64, Constant<U1, f64>, Sine<f64>>>,
+    adsr: Option<Adsr>,
 ) -> An<impl AudioNode<Sample=f64, Inputs=U0, Outputs=U1>> {
-    let wave = waveform * ((pitch * 0.75) * fm) * 1.0 >> saw();
-    let env = lfo(|t| exp(-t * 10.0));
-    (wave * velocity) * env >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
+    let wave = (waveform * ((pitch * 0.75) * fm) * 1.0) >> saw();
+    let env = lfo(move |t| envelope_level(adsr, t));
+    ((wave * velocity) * env) >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
 }
 
 pub fn tri_synth(
@@ -236,10 +353,11 @@ pub fn tri_synth(
     fm: f64,
     velocity: f64,
     waveform: An<Pipe<f64, Constant<U1, f64>, Sine<f64>>>,
+    adsr: Option<Adsr>,
 ) -> An<impl AudioNode<Sample=f64, Inputs=U0, Outputs=U1>> {
-    let wave = waveform * ((pitch * 0.75) * fm) * 1.0 >> triangle();
-    let env = lfo(|t| exp(-t * 10.0));
-    (wave * velocity) * env >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
+    let wave = (waveform * ((pitch * 0.75) * fm) * 1.0) >> triangle();
+    let env = lfo(move |t| envelope_level(adsr, t));
+    ((wave * velocity) * env) >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
 }
 
 pub fn square_synth(
@@ -247,8 +365,43 @@ pub fn square_synth(
     fm: f64,
     velocity: f64,
     waveform: An<Pipe<f64, Constant<U1, f64>, Sine<f64>>>,
+    adsr: Option<Adsr>,
 ) -> An<impl AudioNode<Sample=f64, Inputs=U0, Outputs=U1>> {
-    let wave = waveform * ((pitch * 0.75) * fm) * 1.0 >> square();
-    let env = lfo(|t| exp(-t * 10.0));
-    (wave * velocity) * env >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
-}
\ No newline at end of file
+    let wave = (waveform * ((pitch * 0.75) * fm) * 1.0) >> square();
+    let env = lfo(move |t| envelope_level(adsr, t));
+    ((wave * velocity) * env) >> limiter((0.0, 0.1)) >> declick_s(xerp(0.002, 0.00002, 0.7))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_synth_steals_its_oldest_voice() {
+        let mut voices = vec![60, 62, 64];
+        assert!(steal_oldest(&mut voices, 4).next().is_none());
+        voices.push(65);
+
+        // the fifth note takes the place of the first
+        assert_eq!(steal_oldest(&mut voices, 4).collect::<Vec<_>>(), [60]);
+        voices.push(67);
+        assert_eq!(voices, [62, 64, 65, 67]);
+
+        // a lowered limit steals as many as it takes
+        assert_eq!(steal_oldest(&mut voices, 2).collect::<Vec<_>>(), [62, 64, 65]);
+        assert_eq!(voices, [67]);
+    }
+
+    #[test]
+    fn stopped_and_ended_voices_are_let_go() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let ids = [EventId::new(), EventId::new(), EventId::new(), EventId::new()];
+        // the same note sounding twice, once per overlapping trigger
+        let mut voices = vec![(60, ids[0], now), (62, ids[1], later), (64, ids[2], later), (62, ids[3], later)];
+        let stop = Note { note_number: 62, started: true, duration: 0, ..Default::default() };
+        let still_held = Note { note_number: 64, started: true, duration: 250, ..Default::default() };
+
+        assert_eq!(release_voices(&mut voices, &[stop, still_held], now), [ids[1], ids[3]]);
+        assert_eq!(voices, [(64, ids[2], later)]);
+    }
+}
@@ -0,0 +1,118 @@
+use ratatui::prelude::Color;
+use std::fs::read_to_string;
+
+// interface colors, read from orca/theme.toml when it exists; every missing or unreadable entry keeps the default
+pub struct Theme {
+    pub operator: Color,
+    pub storage: Color,
+    pub port: Color,
+    pub bang: Color,
+    pub comment: Color,
+    pub value: Color,
+    pub marker: Color,
+    pub ruler: Color,
+    // empty cells and the · markers between the + markers
+    pub dot: Color,
+    pub border: Color,
+    // the status line
+    pub status: Color,
+    // the variables inspector and the help popup
+    pub panel: Color,
+    pub selection: Color,
+    // background of every cell locked this tick, named port or not
+    pub locked: Color,
+    // the cell below a halt
+    pub halted: Color,
+    pub cursor: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            operator: Color::Cyan,
+            storage: Color::LightYellow,
+            port: Color::Cyan,
+            bang: Color::White,
+            comment: Color::Reset,
+            value: Color::DarkGray,
+            marker: Color::LightCyan,
+            ruler: Color::DarkGray,
+            dot: Color::DarkGray,
+            border: Color::DarkGray,
+            status: Color::White,
+            panel: Color::Cyan,
+            selection: Color::DarkGray,
+            locked: Color::Indexed(236),
+            halted: Color::LightRed,
+            cursor: Color::Yellow,
+        }
+    }
+}
+
+impl Theme {
+    // only flat `name = "color"` lines are read, colors are ratatui names like "light cyan" or hex like "#ff8800"
+    pub fn load(filename: &str) -> Theme {
+        let mut theme = Theme::default();
+        let Ok(contents) = read_to_string(filename) else {
+            return theme;
+        };
+        for (name, value) in contents
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+        {
+            let Ok(color) = value.trim().trim_matches('"').parse::<Color>() else {
+                continue;
+            };
+            match name.trim() {
+                "operator" => theme.operator = color,
+                "storage" => theme.storage = color,
+                "port" => theme.port = color,
+                "bang" => theme.bang = color,
+                "comment" => theme.comment = color,
+                "value" => theme.value = color,
+                "marker" => theme.marker = color,
+                "ruler" => theme.ruler = color,
+                "dot" => theme.dot = color,
+                "border" => theme.border = color,
+                "status" => theme.status = color,
+                "panel" => theme.panel = color,
+                "selection" => theme.selection = color,
+                "locked" => theme.locked = color,
+                "halted" => theme.halted = color,
+                "cursor" => theme.cursor = color,
+                _ => {}
+            }
+        }
+        theme
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn a_theme_file_sets_the_roles_it_names() {
+        let path = std::env::temp_dir().join(format!("orca-rs-theme-{}.toml", std::process::id()));
+        let lines = [
+            "# light background",
+            "operator = \"light red\"",
+            "bang=\"#ff8800\"",
+            "cursor = \"no such color\"",
+            "unknown = \"blue\"",
+        ];
+        fs::write(&path, lines.join("\n")).unwrap();
+        let theme = Theme::load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(theme.operator, Color::LightRed);
+        assert_eq!(theme.bang, Color::Rgb(0xff, 0x88, 0x00));
+        // roles that are missing or can't be read keep the default palette
+        assert_eq!(theme.cursor, Theme::default().cursor);
+        assert_eq!(theme.port, Theme::default().port);
+        assert_eq!(Theme::load("no_such_theme.toml").operator, Theme::default().operator);
+    }
+}
@@ -1,76 +1,149 @@
 use crate::{
-    context::{Context, Mode},
-    utils::{get_key_name, get_scale_name, HELP},
+    context::{AppState, Context, Mode, Prompt},
+    theme::Theme,
+    operators::{base_36_to_char, char_to_base_36},
+    utils::{get_key_name, get_scale_name, help_text, operator_help},
     Cursor,
 };
-use parking_lot::{lock_api, RawMutex};
+use parking_lot::lock_api::{self, RawMutex};
 use std::{
-    io::Stdout,
+    time::Duration,
     sync::atomic::{AtomicBool, Ordering},
     sync::Arc,
 };
 use ratatui::{
-    backend::CrosstermBackend,
+    backend::Backend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
-    prelude::{Color, Modifier, Style},
+    prelude::{Modifier, Style},
     widgets::{Block, BorderType, Borders, Cell, Clear, Padding, Paragraph, Row, Table},
     Terminal,
 };
 
-pub fn draw(
-    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+const GRID_PADDING_LEFT: u16 = 3;
+const GRID_PADDING_TOP: u16 = 1;
+const VARIABLES_PANEL_WIDTH: u16 = 10;
+const STATUS_MESSAGE_TIME: Duration = Duration::from_secs(3);
+
+// the first grid row and column on screen, kept by the draw loop so the cursor stays visible
+#[derive(Default)]
+pub struct Viewport {
+    pub row: usize,
+    pub col: usize,
+    pub visible_rows: usize,
+    pub visible_cols: usize,
+}
+
+// moves a scroll offset just enough to keep the cursor inside a window of the given size
+pub fn scroll_offset(offset: usize, cursor: usize, visible: usize) -> usize {
+    if cursor < offset {
+        cursor
+    } else if visible > 0 && cursor >= offset + visible {
+        cursor + 1 - visible
+    } else {
+        offset
+    }
+}
+
+// maps a terminal position to the grid cell drawn there, past the border and padding of the grid block
+pub fn grid_cell_at(column: u16, row: u16, viewport: &Viewport, rows: usize, cols: usize) -> Option<(usize, usize)> {
+    // positions past the drawn window, like the status line, are not cells even when the grid goes on
+    let col = Some(column.checked_sub(1 + GRID_PADDING_LEFT)? as usize).filter(|&col| col < viewport.visible_cols)?;
+    let row = Some(row.checked_sub(1 + GRID_PADDING_TOP)? as usize).filter(|&row| row < viewport.visible_rows)?;
+    let (row, col) = (row + viewport.row, col + viewport.col);
+    (row < rows && col < cols).then_some((row, col))
+}
+
+// a grid row or column index in base-36, the way operators count
+pub fn ruler_label(index: usize) -> String {
+    let mut index = index;
+    let mut label = vec![base_36_to_char((index % 36) as u8, false)];
+    while index >= 36 {
+        index /= 36;
+        label.push(base_36_to_char((index % 36) as u8, false));
+    }
+    label.iter().rev().collect()
+}
+
+// generic over the backend and the lock so a test can draw into a buffer and count how often it locks
+#[allow(clippy::too_many_arguments)]
+pub fn draw<B: Backend, R: RawMutex>(
+    terminal: &mut Terminal<B>,
     cursor: &Cursor,
     mode: &mut Mode,
     should_redraw: &Arc<AtomicBool>,
-    context_arc: &Arc<lock_api::Mutex<RawMutex, Context>>,
+    context_arc: &Arc<lock_api::Mutex<R, Context>>,
     show_popup: bool,
+    show_variables: bool,
+    viewport: &mut Viewport,
+    theme: &Theme,
+    halt_glyph: Option<char>,
+    prompt: Option<&Prompt>,
 ) {
     terminal
         .draw(|f| {
-            let (grid, tempo, divisions) = {
-                let context = context_arc.lock();
-                (context.grid.clone(), context.tempo, context.divisions)
-            };
-
-            let port_name = {
+            // everything the frame shows is copied out under a single lock, so the tick thread only waits
+            // for the copies and not for the layout and styling below
+            let (grid, locks, cols, grid_spacing, variables, statusline_text) = {
                 let context = context_arc.lock();
-                if context.is_port(*cursor.cursor_row, *cursor.cursor_col) {
-                    let name = context
-                        .get_port_name(*cursor.cursor_row, *cursor.cursor_col)
-                        .unwrap_or(&"".to_string())
-                        .clone();
-                    if name == "Global Scale" {
-                        let scale_value = context.grid[*cursor.cursor_row][*cursor.cursor_col];
-                        if let Some(scale_name) = get_scale_name(scale_value) {
-                            format!("{}: {}", name, scale_name)
-                        } else {
-                            name
-                        }
-                    } else {
-                        name
-                    }
+                let port_name = port_name(&context, cursor);
+                let mut variables: Vec<(char, char)> = if show_variables {
+                    context.variables_snapshot.iter().map(|(&name, &value)| (name, value)).collect()
                 } else {
-                    "".to_string()
-                }
+                    Vec::new()
+                };
+                variables.sort();
+                (
+                    context.grid.clone(),
+                    context.locks.clone(),
+                    context.cols,
+                    context.grid_spacing.max(1),
+                    variables,
+                    status_line_text(&context, cursor, mode, port_name, prompt),
+                )
             };
+            // even spacings get a dimmer marker halfway between the + markers
+            let half_spacing = (grid_spacing.is_multiple_of(2) && grid_spacing > 2).then_some(grid_spacing / 2);
 
             let chunk = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([Constraint::Min(10), Constraint::Max(3)].as_ref())
                 .split(f.size());
+            // the variables inspector takes a narrow column right of the grid, so the grid keeps its origin
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Min(10),
+                    Constraint::Length(if show_variables { VARIABLES_PANEL_WIDTH } else { 0 }),
+                ])
+                .split(chunk[0]);
+            let grid_area = columns[0];
+
+            // the grid area inside the border and padding, scrolled to keep the cursor on screen
+            let visible_rows = grid_area.height.saturating_sub(2 + 2 * GRID_PADDING_TOP) as usize;
+            let visible_cols = grid_area.width.saturating_sub(2 + 2 * GRID_PADDING_LEFT) as usize;
+            viewport.row = scroll_offset(viewport.row, *cursor.cursor_row, visible_rows);
+            viewport.col = scroll_offset(viewport.col, *cursor.cursor_col, visible_cols);
+            viewport.visible_rows = visible_rows;
+            viewport.visible_cols = visible_cols;
 
             let rows = grid
                 .iter()
                 .enumerate()
+                .skip(viewport.row)
+                .take(visible_rows)
                 .map(|(r, row)| {
                     let cells = row
                         .iter()
                         .enumerate()
+                        .skip(viewport.col)
+                        .take(visible_cols)
                         .map(|(c, &value)| {
                             let display_value = if value != '.' {
                                 value
-                            } else if r % 9 == 0 && c % 9 == 0 {
+                            } else if r.is_multiple_of(grid_spacing) && c.is_multiple_of(grid_spacing) {
                                 '+'
+                            } else if half_spacing.is_some_and(|half| r.is_multiple_of(half) && c.is_multiple_of(half)) {
+                                '·'
                             } else {
                                 '.'
                             };
@@ -79,7 +152,6 @@ pub fn draw(
                             let mut style = Style::default();
 
                             let is_selected = {
-                                //let _context = context_arc.lock();
                                 if let Mode::Select { start, end } = mode {
                                     let row_in_range = (r >= start.0 && r <= end.0)
                                         || (r <= start.0 && r >= end.0);
@@ -91,62 +163,72 @@ pub fn draw(
                                 }
                             };
 
+                            let is_locked = locks.contains(&(r as i32, c as i32));
                             if is_selected {
-                                style = style.bg(Color::DarkGray);
+                                style = style.bg(theme.selection);
+                            } else if is_locked {
+                                style = style.bg(theme.locked);
                             }
 
                             if *cursor.cursor_row == r && *cursor.cursor_col == c {
-                                style = style.fg(Color::Yellow).add_modifier(Modifier::REVERSED);
+                                style = style.fg(theme.cursor).add_modifier(Modifier::REVERSED);
+                            } else if is_locked && r > 0 && Some(grid[r - 1][c]) == halt_glyph {
+                                style = style.fg(theme.halted).add_modifier(Modifier::BOLD);
+                            } else if is_locked {
+                                match display_value {
+                                    'E' | 'W' | 'N' | 'S' => {
+                                        style =
+                                            style.fg(theme.port).add_modifier(Modifier::DIM);
+                                    }
+                                    '*' => {
+                                        style = style
+                                            .fg(theme.bang)
+                                            .add_modifier(Modifier::REVERSED);
+                                    }
+                                    _ => {
+                                        style = style
+                                            .fg(theme.port)
+                                            .add_modifier(Modifier::UNDERLINED)
+                                    }
+                                }
                             } else {
-                                let context = context_arc.lock();
-                                if context.is_port(r, c) {
-                                    match display_value {
-                                        'E' | 'W' | 'N' | 'S' => {
-                                            style =
-                                                style.fg(Color::Cyan).add_modifier(Modifier::DIM);
-                                        }
-                                        '*' => {
-                                            style = style
-                                                .fg(Color::White)
-                                                .add_modifier(Modifier::REVERSED);
-                                        }
-                                        _ => {
-                                            style = style
-                                                .fg(Color::Cyan)
-                                                .add_modifier(Modifier::UNDERLINED)
-                                        }
+                                match display_value {
+                                    'A'..='Z' => {
+                                        style = style
+                                            .fg(theme.operator)
+                                            .add_modifier(Modifier::REVERSED)
                                     }
-                                } else {
-                                    match display_value {
-                                        'A'..='Z' => {
-                                            style = style
-                                                .fg(Color::Cyan)
-                                                .add_modifier(Modifier::REVERSED)
-                                        }
-                                        '{' | '}' | '[' | ']' | '@' => {
-                                            style = style
-                                                .fg(Color::LightYellow)
-                                                .add_modifier(Modifier::REVERSED)
-                                        }
-                                        '^' | '~' | ':' | ';' | '|' | '>' | '?' => {
-                                            style = style
-                                                .fg(Color::Cyan)
-                                                .add_modifier(Modifier::REVERSED)
-                                        }
-                                        'a'..='z' | '0'..='9' => {
-                                            style = style.fg(Color::DarkGray);
-                                        }
-                                        '.' => {
-                                            style = style
-                                                .fg(Color::DarkGray)
-                                                .add_modifier(Modifier::DIM);
-                                        }
-                                        '+' => {
-                                            style = style.fg(Color::LightCyan)
-                                            //.add_modifier(Modifier::DIM);
-                                        }
-                                        _ => {}
+                                    '{' | '}' | '[' | ']' | '@' => {
+                                        style = style
+                                            .fg(theme.storage)
+                                            .add_modifier(Modifier::REVERSED)
+                                    }
+                                    '^' | '~' | ':' | ';' | '|' | '>' | '?' | '%' | '/' | '&' | ')' | '!' | '$' | '<' | '(' | '\'' => {
+                                        style = style
+                                            .fg(theme.operator)
+                                            .add_modifier(Modifier::REVERSED)
+                                    }
+                                    '#' => {
+                                        style = style.fg(theme.comment);
+                                    }
+                                    'a'..='z' | '0'..='9' => {
+                                        style = style.fg(theme.value);
                                     }
+                                    '.' => {
+                                        style = style
+                                            .fg(theme.dot)
+                                            .add_modifier(Modifier::DIM);
+                                    }
+                                    '+' => {
+                                        style = style.fg(theme.marker)
+                                        //.add_modifier(Modifier::DIM);
+                                    }
+                                    '·' => {
+                                        style = style
+                                            .fg(theme.dot)
+                                            .add_modifier(Modifier::DIM);
+                                    }
+                                    _ => {}
                                 }
                             }
                             cell.style(style)
@@ -156,10 +238,7 @@ pub fn draw(
                 })
                 .collect::<Vec<_>>();
 
-            let constraints = {
-                let context = context_arc.lock();
-                vec![Constraint::Length(1); context.cols]
-            };
+            let constraints = vec![Constraint::Length(1); cols];
 
             let table = Table::new(rows)
                 .widths(&constraints)
@@ -167,25 +246,80 @@ pub fn draw(
                 .block(
                     Block::default()
                         .padding(Padding {
-                            left: 3,
+                            left: GRID_PADDING_LEFT,
                             right: 3,
-                            top: 1,
+                            top: GRID_PADDING_TOP,
                             bottom: 1,
                         })
                         .border_type(BorderType::Rounded)
                         .border_style(
                             Style::default()
-                                .fg(Color::DarkGray)
+                                .fg(theme.border)
                                 .add_modifier(Modifier::DIM),
                         )
                         .borders(Borders::ALL),
                 );
-            f.render_widget(table, chunk[0]);
+            f.render_widget(table, grid_area);
 
-            let statusline_text =
-                status_line_text(context_arc, tempo, divisions, cursor, mode, port_name);
+            if show_variables {
+                let variable_rows = variables
+                    .into_iter()
+                    .map(|(name, value)| Row::new(vec![Cell::from(name.to_string()), Cell::from(value.to_string())]));
+                let variables_table = Table::new(variable_rows)
+                    .widths(&[Constraint::Length(2), Constraint::Length(2)])
+                    .style(Style::default().fg(theme.panel))
+                    .block(
+                        Block::default()
+                            .title(" V ")
+                            .padding(Padding::horizontal(1))
+                            .border_type(BorderType::Rounded)
+                            .border_style(Style::default().fg(theme.border).add_modifier(Modifier::DIM))
+                            .borders(Borders::ALL),
+                    );
+                f.render_widget(variables_table, columns[1]);
+            }
+
+            // base-36 rulers in the padding around the grid, columns labelled at the same steps as the + markers
+            let ruler_style = Style::default().fg(theme.ruler).add_modifier(Modifier::DIM);
+            let grid_left = grid_area.x + 1 + GRID_PADDING_LEFT;
+            let grid_top = grid_area.y + 1 + GRID_PADDING_TOP;
+            let grid_width = grid_area.width.saturating_sub(2 + GRID_PADDING_LEFT);
+            let grid_height = grid_area.height.saturating_sub(2 + GRID_PADDING_TOP);
+            let grid_cols = grid.first().map_or(0, |row| row.len());
+            let mut column_ruler = vec![' '; visible_cols];
+            for col in (viewport.col..grid_cols.min(viewport.col + visible_cols)).filter(|col| col.is_multiple_of(grid_spacing)) {
+                for (i, label_char) in ruler_label(col).chars().enumerate() {
+                    if let Some(ruler_char) = column_ruler.get_mut(col - viewport.col + i) {
+                        *ruler_char = label_char;
+                    }
+                }
+            }
+            let column_ruler: String = column_ruler.into_iter().collect();
+            let row_ruler: Vec<String> = (viewport.row..grid.len().min(viewport.row + visible_rows))
+                .map(|row| format!("{:>2}", ruler_label(row)))
+                .collect();
+            f.render_widget(
+                Paragraph::new(column_ruler).style(ruler_style),
+                Rect::new(grid_left, grid_top - 1, grid_width, 1),
+            );
+            f.render_widget(
+                Paragraph::new(row_ruler.join("\n")).style(ruler_style),
+                Rect::new(grid_area.x + 1, grid_top, GRID_PADDING_LEFT - 1, grid_height),
+            );
+
+            // cut what doesn't fit inside the padding, marking the cut so it doesn't look like the end
+            let status_width = chunk[1].width.saturating_sub(6) as usize;
+            let statusline_text = if statusline_text.chars().count() > status_width {
+                statusline_text
+                    .chars()
+                    .take(status_width.saturating_sub(1))
+                    .chain(std::iter::once('…'))
+                    .collect()
+            } else {
+                statusline_text
+            };
             let statusline = Paragraph::new(statusline_text)
-                .style(Style::default().fg(Color::White))
+                .style(Style::default().fg(theme.status))
                 .alignment(Alignment::Left)
                 .block(
                     Block::default()
@@ -202,8 +336,8 @@ pub fn draw(
 
             let size = f.size();
             if show_popup {
-                let block = Paragraph::new(HELP.trim().to_string())
-                    .style(Style::default().fg(Color::Cyan))
+                let block = Paragraph::new(help_text().trim().to_string())
+                    .style(Style::default().fg(theme.panel))
                     .alignment(Alignment::Left)
                     .block(Block::default().borders(Borders::ALL));
                 let area = help_rect(80, 80, size);
@@ -216,31 +350,98 @@ pub fn draw(
     should_redraw.store(false, Ordering::Relaxed);
 }
 
+// the port under the cursor, with the scale or sample file it selects where that helps
+fn port_name(context: &Context, cursor: &Cursor) -> String {
+    if context.is_port(*cursor.cursor_row, *cursor.cursor_col) {
+        let name = context
+            .get_port_name(*cursor.cursor_row, *cursor.cursor_col)
+            .unwrap_or(&"".to_string())
+            .clone();
+        if name == "Global Scale" {
+            let scale_value = context.grid[*cursor.cursor_row][*cursor.cursor_col];
+            if let Some(scale_name) = get_scale_name(scale_value) {
+                format!("{}: {}", name, scale_name)
+            } else {
+                name
+            }
+        } else if name == "Sample" {
+            // the sampler's bank port sits six cells right of its sample port
+            let (row, col) = (*cursor.cursor_row as i32, *cursor.cursor_col as i32);
+            let (sample, _) = char_to_base_36(context.read(row, col));
+            let (bank, _) = char_to_base_36(context.read(row, col + 6));
+            match context
+                .sample_names
+                .get(bank as usize)
+                .and_then(|names| names.get((sample as usize).checked_rem(names.len())?))
+            {
+                Some(file_name) => format!("{}: {}", name, file_name),
+                None => name,
+            }
+        } else {
+            name
+        }
+    } else {
+        "".to_string()
+    }
+}
+
 fn status_line_text(
-    context_arc: &Arc<lock_api::Mutex<RawMutex, Context>>,
-    tempo: u64,
-    divisions: u64,
+    context: &Context,
     cursor: &Cursor<'_>,
-    mode: &mut Mode,
+    mode: &Mode,
     port_name: String,
+    prompt: Option<&Prompt>,
 ) -> String {
-    let context = context_arc.lock();
+    let operator = context
+        .grid
+        .get(*cursor.cursor_row)
+        .and_then(|row| row.get(*cursor.cursor_col))
+        .and_then(|glyph| operator_help().get(glyph).copied())
+        .unwrap_or("");
+    // a fresh status message takes the place of the operator help for a few seconds
+    let operator = match &context.status_message {
+        Some((message, shown_at)) if shown_at.elapsed() < STATUS_MESSAGE_TIME => message.as_str(),
+        _ => operator,
+    };
     format!(
-        "{} bpm   {}/4   {},{}  {}  {}   {} {}   {} ",
-        tempo,
-        divisions,
+        "{} {:<6} {} bpm{}   {}/4   swing {}%   vol {}% {}   {},{}  {}{}  {}   {} {}   {}   {} ",
+        if context.app_state == AppState::Running { '▶' } else { '⏸' },
+        context.ticks,
+        context.tempo,
+        if context.metronome { " (click)" } else { "" },
+        context.divisions,
+        context.swing,
+        (context.master_volume.value() * 100.0).round(),
+        context.velocity_curve.name(),
         cursor.cursor_row,
         cursor.cursor_col,
         context.midi_port_name,
-        match mode {
-            Mode::Normal => "Insert".to_string(),
-            Mode::Select { start: _, end: _ } => "Select".to_string(),
-            Mode::Copy => "Copy".to_string(),
-            Mode::Move => "Move".to_string(),
+        if context.clock_follow {
+            " (follow)"
+        } else if context.midi_clock {
+            " (clock)"
+        } else {
+            ""
+        },
+        match (prompt, mode) {
+            (Some(Prompt::Jump(input)), _) => format!("Jump to {}_", input),
+            (Some(Prompt::Replace(input)), _) => {
+                let mut glyphs = input.chars();
+                match (glyphs.next(), glyphs.next()) {
+                    (Some(from), Some(to)) => format!("Replace {} with {}", from, to),
+                    (Some(from), None) => format!("Replace {} with _", from),
+                    _ => "Replace _".to_string(),
+                }
+            }
+            (None, Mode::Normal) => "Insert".to_string(),
+            (None, Mode::Select { start: _, end: _ }) => "Select".to_string(),
+            (None, Mode::Copy) => "Copy".to_string(),
+            (None, Mode::Move) => "Move".to_string(),
         },
         get_key_name(context.global_key).expect("Failed to get key name"),
         get_scale_name(context.global_scale).expect("Failed to get scale name"),
-        &port_name
+        &port_name,
+        operator,
     )
 }
 
@@ -269,3 +470,132 @@ fn help_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         )
         .split(popup_layout[1])[1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_click_maps_to_the_cell_past_the_border_and_padding() {
+        let viewport = Viewport { row: 0, col: 0, visible_rows: 10, visible_cols: 20 };
+        assert_eq!(grid_cell_at(4, 2, &viewport, 10, 20), Some((0, 0)));
+        assert_eq!(grid_cell_at(9, 5, &viewport, 10, 20), Some((3, 5)));
+        // the border and padding are not cells
+        assert_eq!(grid_cell_at(3, 2, &viewport, 10, 20), None);
+        assert_eq!(grid_cell_at(4, 1, &viewport, 10, 20), None);
+        // nor is anything past the grid or the drawn window
+        assert_eq!(grid_cell_at(4, 12, &viewport, 10, 20), None);
+        assert_eq!(grid_cell_at(9, 5, &viewport, 3, 20), None);
+
+        // a scrolled grid offsets the cell
+        let viewport = Viewport { row: 5, col: 30, visible_rows: 10, visible_cols: 20 };
+        assert_eq!(grid_cell_at(4, 2, &viewport, 40, 60), Some((5, 30)));
+    }
+
+    #[test]
+    fn ruler_labels_count_in_base_36() {
+        assert_eq!(ruler_label(0), "0");
+        assert_eq!(ruler_label(35), "z");
+        assert_eq!(ruler_label(36), "10");
+        assert_eq!(ruler_label(37), "11");
+        assert_eq!(ruler_label(36 * 36), "100");
+    }
+
+    #[test]
+    fn the_scroll_offset_keeps_the_cursor_in_view() {
+        // inside the window nothing moves
+        assert_eq!(scroll_offset(0, 5, 10), 0);
+        assert_eq!(scroll_offset(4, 13, 10), 4);
+        // past either edge the window moves just far enough
+        assert_eq!(scroll_offset(0, 10, 10), 1);
+        assert_eq!(scroll_offset(0, 25, 10), 16);
+        assert_eq!(scroll_offset(8, 3, 10), 3);
+        // a window too small to draw anything leaves the offset alone
+        assert_eq!(scroll_offset(2, 30, 0), 2);
+    }
+
+    static DRAW_LOCKS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    // a parking_lot mutex that counts how often it is locked
+    struct CountingRawMutex(parking_lot::RawMutex);
+
+    unsafe impl RawMutex for CountingRawMutex {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const INIT: CountingRawMutex = CountingRawMutex(parking_lot::RawMutex::INIT);
+        type GuardMarker = lock_api::GuardSend;
+
+        fn lock(&self) {
+            DRAW_LOCKS.fetch_add(1, Ordering::Relaxed);
+            self.0.lock();
+        }
+
+        fn try_lock(&self) -> bool {
+            DRAW_LOCKS.fetch_add(1, Ordering::Relaxed);
+            self.0.try_lock()
+        }
+
+        unsafe fn unlock(&self) {
+            self.0.unlock();
+        }
+    }
+
+    #[test]
+    fn a_frame_locks_the_context_once() {
+        let mut context = Context::new(120, 4, 8, 16, "new", Some(0), "operator_config.txt");
+        context.write(0, 0, 'D');
+        let context_arc = Arc::new(lock_api::Mutex::<CountingRawMutex, Context>::new(context));
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(60, 20)).unwrap();
+        let (mut cursor_row, mut cursor_col) = (0, 0);
+        let cursor = Cursor { cursor_row: &mut cursor_row, cursor_col: &mut cursor_col };
+
+        for show_variables in [false, true] {
+            DRAW_LOCKS.store(0, Ordering::Relaxed);
+            draw(
+                &mut terminal,
+                &cursor,
+                &mut Mode::Normal,
+                &Arc::new(AtomicBool::new(true)),
+                &context_arc,
+                false,
+                show_variables,
+                &mut Viewport::default(),
+                &Theme::default(),
+                Some('H'),
+                None,
+            );
+            assert_eq!(DRAW_LOCKS.load(Ordering::Relaxed), 1);
+        }
+        assert!(!context_arc.is_locked());
+    }
+
+    #[test]
+    fn a_remapped_halt_styles_the_cell_it_halts() {
+        let mut context = Context::new(120, 4, 4, 4, "new", Some(0), "operator_config.txt");
+        context.write(0, 1, 'Q');
+        context.write(1, 1, '5');
+        context.lock(1, 1);
+        let context_arc = Arc::new(lock_api::Mutex::<CountingRawMutex, Context>::new(context));
+        let (mut cursor_row, mut cursor_col) = (3, 3);
+        let cursor = Cursor { cursor_row: &mut cursor_row, cursor_col: &mut cursor_col };
+        let theme = Theme::default();
+        let halted_fg = |halt_glyph| {
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 16)).unwrap();
+            draw(
+                &mut terminal,
+                &cursor,
+                &mut Mode::Normal,
+                &Arc::new(AtomicBool::new(true)),
+                &context_arc,
+                false,
+                false,
+                &mut Viewport::default(),
+                &theme,
+                halt_glyph,
+                None,
+            );
+            terminal.backend().buffer().get(2 + GRID_PADDING_LEFT, 2 + GRID_PADDING_TOP).fg
+        };
+        assert_eq!(halted_fg(Some('Q')), theme.halted);
+        assert_ne!(halted_fg(Some('H')), theme.halted);
+    }
+}
@@ -1,32 +1,98 @@
-pub const HELP: &str = "
-OPERATORS
-[A]dd: Outputs sum of inputs.               [B] subtract: Outputs difference of inputs.
-[C]lock: Outputs modulo of frame.           [D]elay: Bangs on modulo of frame.
-[E]ast: Moves eastward, or bangs.           [F] if: Bangs if inputs are equal.
-[G]enerator: Writes operands with offset.   [H]alt: Halts southward operand.
-[I]ncrement: Increments southward operand.  [J]umper: Outputs northward operand.
-[K]onkat: Reads multiple variables.         [L]ess: Outputs smallest of inputs.
-[M]ultiply: Outputs product of inputs.      [N]orth: Moves Northward, or bangs.
-[O] read: Reads operand with offset.        [P]ush: Writes eastward operand.
-[Q]uery: Reads operands with offset.        [R]andom: Outputs random value.
-[S]outh: Moves southward, or bangs.         [T]rack: Reads eastward operand.
-[U]clid: Bangs on Euclidean rhythm.         [V]ariable: Reads and writes variable.
-[W]est: Moves westward, or bangs.           [X] write: Writes operand with offset.
-[Y] jymper: Outputs westward operand.       [Z] lerp: Transitions operand to input.
-[*] bang: Bangs neighboring operands.       [#] comment: Halts a line.
-[:] MIDI: Sends a MIDI note.                [;] scaler: Sends degree of a scale as a MIDI note
-[>] sampler: Plays a sample                 [~] synth: Plays a built-in synth's note
-[{] snippet save: Saves a snippet on bang   [}] snippet load: Loads a snippet on bang
-[[] save: Saves to a file on bang           []] load: Loads a file on bang
-[@] globals: Global key and scale
+use std::collections::HashMap;
 
+// one line per operator glyph, used for the help popup and for the operator under the cursor
+pub const OPERATOR_HELP: [(char, &str); 49] = [
+    ('A', "Add: Outputs sum of inputs."),
+    ('B', "Subtract: Outputs difference of inputs."),
+    ('C', "Clock: Outputs modulo of frame."),
+    ('D', "Delay: Bangs on modulo of frame."),
+    ('E', "East: Moves eastward, or bangs."),
+    ('F', "If: Bangs if inputs are equal."),
+    ('G', "Generator: Writes operands with offset."),
+    ('H', "Halt: Stops the operand below."),
+    ('I', "Increment: Increments southward operand."),
+    ('J', "Jumper: Outputs northward operand."),
+    ('K', "Konkat: Reads multiple variables."),
+    ('L', "Less: Outputs smallest of inputs."),
+    ('M', "Multiply: Outputs product of inputs."),
+    ('N', "North: Moves Northward, or bangs."),
+    ('O', "Read: Reads operand with offset."),
+    ('P', "Push: Writes eastward operand."),
+    ('Q', "Query: Reads operands with offset."),
+    ('R', "Random: Outputs random value."),
+    ('S', "South: Moves southward, or bangs."),
+    ('T', "Track: Reads eastward operand."),
+    ('U', "Uclid: Bangs on Euclidean rhythm."),
+    ('V', "Variable: Reads and writes variable."),
+    ('W', "West: Moves westward, or bangs."),
+    ('X', "Write: Writes operand with offset."),
+    ('Y', "Jymper: Outputs westward operand."),
+    ('Z', "Lerp: Transitions operand to input."),
+    ('*', "Bang: Bangs neighboring operands."),
+    ('#', "Comment: Halts a line."),
+    (':', "MIDI: Sends a MIDI note."),
+    (';', "Scaler: Sends a scale degree as MIDI."),
+    ('>', "Sampler: Plays a sample."),
+    ('~', "Synth: Plays a built-in synth's note."),
+    ('{', "Snippet save: Saves a snippet on bang."),
+    ('}', "Snippet load: Loads a snippet on bang."),
+    ('[', "Save: Saves to a file on bang."),
+    (']', "Load: Loads a file on bang."),
+    ('@', "Globals: Global key and scale."),
+    ('%', "Modulo: Outputs modulo of inputs."),
+    ('/', "Divide: Outputs quotient of inputs."),
+    ('&', "MIDI in: Outputs last received MIDI note."),
+    (')', "Greater: Outputs largest of inputs."),
+    ('|', "Quantize: Snaps semitones to a degree."),
+    ('!', "Pitch bend: Sends MIDI pitch bend."),
+    ('$', "Chance: Fires the operator below by chance."),
+    ('<', "Arp: Plays scale degrees in turn."),
+    ('(', "Chord: Sends a MIDI chord on bang."),
+    ('?', "MIDI CC: Sends a MIDI control change."),
+    ('^', "Bernoulli: Bangs one of two outputs."),
+    ('\'', "Note name: Spells a note number as a key."),
+];
+
+const CONTROLS_HELP: &str = "
 CONTROLS
 [`]: select mode      [/]: move mode
 [=/-]: tempo up/down  [CTRL-c]: copy selected cells
 [CTRL-v]: paste       [CTRL-d]: clear the grid
 [CTRL-h]: help        [CTRL-p]: change midi port
+[CTRL-k]: midi clock   [CTRL-x]: midi panic
+[+/_]: divisions up/down  [CTRL-r]: reload samples
+[CTRL-up/down]: volume  [mouse]: click to move, drag to select
+[ALT-v/V]: paste a step up/down
+[ALT-r/R]: insert/delete row  [ALT-c/C]: insert/delete column
+[CTRL-g]: jump to row,col  [ALT-up/down]: step the value under the cursor
+[ALT-f/F]: flip the selection horizontally/vertically  [ALT-t]: rotate it
+[CTRL-right/left]: swing up/down  [CTRL-e]: show variables
+[CTRL-t]: MIDI velocity curve (lin, exp, log)  [CTRL-b]: metronome
+[CTRL-o]: export to orca/sessions/export.orca
+[ALT-SHIFT-down/up]: add/remove a grid row  [ALT-SHIFT-right/left]: add/remove a grid column
+[CTRL-f]: replace a glyph (type old, new, enter; ALT-enter skips locked cells)
 ";
 
+pub fn operator_help() -> HashMap<char, &'static str> {
+    OPERATOR_HELP.into_iter().collect()
+}
+
+// the help popup, with the operators laid out in two columns
+pub fn help_text() -> String {
+    let lines: Vec<String> = OPERATOR_HELP
+        .chunks(2)
+        .map(|pair| {
+            pair.iter()
+                .map(|(glyph, help)| format!("[{}] {}", glyph, help))
+                .map(|entry| format!("{:<48}", entry))
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    format!("OPERATORS\n{}\n{}", lines.join("\n"), CONTROLS_HELP)
+}
+
 pub const NATURAL_NOTES: [u8; 7] = [9, 11, 0, 2, 4, 5, 7];
 pub const SHARP_NOTES: [u8; 7] = [10, 12, 1, 3, 5, 6, 8];
 pub const SCALES: [[u8; 7]; 26] = [
@@ -83,6 +149,37 @@ pub const SCALES: [[u8; 7]; 26] = [
     //ahirbhairav
     [0, 1, 4, 5, 7, 9, 10],
 ];
+pub const CHORDS: [&[u8]; 10] = [
+    //major
+    &[0, 4, 7],
+    //minor
+    &[0, 3, 7],
+    //diminished
+    &[0, 3, 6],
+    //augmented
+    &[0, 4, 8],
+    //sus2
+    &[0, 2, 7],
+    //sus4
+    &[0, 5, 7],
+    //major seventh
+    &[0, 4, 7, 11],
+    //minor seventh
+    &[0, 3, 7, 10],
+    //dominant seventh
+    &[0, 4, 7, 10],
+    //diminished seventh
+    &[0, 3, 6, 9],
+];
+
+// pitch classes in the same glyphs the Midi note port and the Globals key use
+pub const KEY_CHARS: [char; 12] = ['C', 'c', 'D', 'd', 'E', 'F', 'f', 'G', 'g', 'A', 'a', 'B'];
+
+// inverse of Note::from_base_36: splits a MIDI note number into an octave and a key glyph
+pub fn note_to_key(note_number: u8) -> (u8, char) {
+    let octave = (note_number / 12).saturating_sub(1);
+    (octave, KEY_CHARS[(note_number % 12) as usize])
+}
 
 pub fn get_scale_name(value: char) -> Option<&'static str> {
     match value {
@@ -133,3 +230,34 @@ pub fn get_key_name(value: char) -> Option<&'static str> {
         _ => Some("C"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operator_help_is_looked_up_by_glyph() {
+        let help = operator_help();
+        assert_eq!(help.get(&'R'), Some(&"Random: Outputs random value."));
+        assert_eq!(help.get(&'T'), Some(&"Track: Reads eastward operand."));
+        assert_eq!(help.get(&'*'), Some(&"Bang: Bangs neighboring operands."));
+        assert_eq!(help.get(&'$'), Some(&"Chance: Fires the operator below by chance."));
+        assert_eq!(help.get(&'.'), None);
+        assert_eq!(help.get(&'5'), None);
+        // every glyph has one entry
+        assert_eq!(help.len(), OPERATOR_HELP.len());
+    }
+
+    #[test]
+    fn note_numbers_invert_to_octave_and_key() {
+        assert_eq!(note_to_key(60), (4, 'C'));
+        assert_eq!(note_to_key(61), (4, 'c'));
+        assert_eq!(note_to_key(69), (4, 'A'));
+        assert_eq!(note_to_key(11), (0, 'B'));
+        assert_eq!(note_to_key(0), (0, 'C'));
+        assert_eq!(note_to_key(127), (9, 'G'));
+        // each semitone of an octave gets its own key char
+        let keys: Vec<char> = (48..60).map(|note_number| note_to_key(note_number).1).collect();
+        assert_eq!(keys, KEY_CHARS);
+    }
+}